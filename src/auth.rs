@@ -1,10 +1,13 @@
 use crate::model::{PartialUser, User, Settable};
 use crate::send_mail::Email;
-use actix::Addr;
-use actix_redis::{Command, RedisActor, RespValue};
+use crate::error::ApiError;
+use crate::store::{self, Store, StoreError};
 use futures::future::join;
-use redis_async::resp_array;
-use actix_web::{web, http, Error};
+use actix_web::{dev::Payload, http, web, FromRequest, HttpRequest};
+use futures::future::LocalBoxFuture;
+use chrono::Utc;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use bs58::encode;
 use dotenv::dotenv;
@@ -16,12 +19,160 @@ pub fn generate_temp_code(p_user: &PartialUser) -> String {
     encode(format!("{:x}", Sha256::digest(salted.as_bytes()))).into_string()
 }
 
-// FIXME: access token is always the same!!
-pub fn generate_access_token(user: &User) -> String {
+// a fresh random token per login, see `create_session`
+pub fn generate_access_token() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    encode(bytes).into_string()
+}
+
+/// sessions are listed and revoked by this id, so the token itself
+/// never has to leave the login response
+pub fn session_id(token: &str) -> String {
+    encode(Sha256::digest(token.as_bytes())).into_string()
+}
+
+fn session_ttl() -> usize {
     dotenv().ok();
-    let salt = std::env::var("SALT_ACCESS_TOKEN").expect("evn var 'SALT_ACCESS_TOKEN missing'");
-    let salted = format!("{}{}", salt, user.id());
-    encode(format!("{:x}", Sha256::digest(salted.as_bytes()))).into_string()
+    std::env::var("ACCESS_TOKEN_TTL")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(DEFAULT_SESSION_TTL)
+}
+
+// 30 days
+const DEFAULT_SESSION_TTL: usize = 60 * 60 * 24 * 30;
+
+/// one login. stored under `session:{id}` and listed in `sessions:{user_id}`,
+/// next to `access_token:{token}` which maps the token to the user.
+/// all of them expire together.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub user_id: String,
+    pub created_at: i64,
+    pub expires_at: i64,
+    #[serde(skip_serializing)]
+    token: String,
+}
+
+// the stored form has to keep the token, `Session` only shows it to us
+#[derive(Serialize)]
+struct StoredSession<'a> {
+    id: &'a str,
+    user_id: &'a str,
+    created_at: i64,
+    expires_at: i64,
+    token: &'a str,
+}
+
+/// logs the user in, returns the new access token
+pub async fn create_session(
+    store: &web::Data<dyn Store>,
+    user_id: &str,
+) -> Result<String, StoreError> {
+    let token = generate_access_token();
+    let id = session_id(&token);
+    let ttl = session_ttl();
+    let created_at = Utc::now().timestamp();
+
+    let session = serde_json::to_string(&StoredSession {
+        id: &id,
+        user_id,
+        created_at,
+        expires_at: created_at + ttl as i64,
+        token: &token,
+    })
+    .expect("Session should be Serializable");
+
+    let token_domain = format!("access_token:{}", &token);
+    let session_domain = format!("session:{}", &id);
+    let sessions_domain = format!("sessions:{}", user_id);
+
+    let set_token = store.set_expiring(&token_domain, user_id.as_bytes(), ttl);
+    let set_session = store.set_expiring(&session_domain, session.as_bytes(), ttl);
+    let list = store.add_member(&sessions_domain, id.as_bytes());
+
+    let (set_token, (set_session, list)) = join(set_token, join(set_session, list)).await;
+    set_token.and(set_session).and(list)?;
+
+    Ok(token)
+}
+
+async fn get_session(
+    store: &web::Data<dyn Store>,
+    id: &str,
+) -> Result<Option<Session>, StoreError> {
+    let domain = format!("session:{}", id);
+
+    match store.get(&domain).await? {
+        Some(x) => serde_json::from_slice(&x)
+            .map(Some)
+            .map_err(|_| StoreError::Corrupt(format!("{} is not a Session", domain))),
+        None => Ok(None),
+    }
+}
+
+/// live sessions of a user, expired ones are dropped from the list on the way
+pub async fn list_sessions(
+    store: &web::Data<dyn Store>,
+    user_id: &str,
+) -> Result<Vec<Session>, StoreError> {
+    let sessions_domain = format!("sessions:{}", user_id);
+    let now = Utc::now().timestamp();
+
+    let mut sessions = Vec::new();
+
+    for id in store.members(&sessions_domain).await? {
+        let id = String::from_utf8(id)
+            .map_err(|_| StoreError::Corrupt(format!("{} has a bad session id", sessions_domain)))?;
+
+        match get_session(store, &id).await? {
+            Some(session) if session.expires_at > now => sessions.push(session),
+            _ => store.remove_member(&sessions_domain, id.as_bytes()).await?,
+        }
+    }
+
+    sessions.sort_by_key(|s| s.created_at);
+    Ok(sessions)
+}
+
+/// returns false if the user has no such session
+pub async fn revoke_session(
+    store: &web::Data<dyn Store>,
+    user_id: &str,
+    id: &str,
+) -> Result<bool, StoreError> {
+    let session = match get_session(store, id).await? {
+        Some(x) if x.user_id == user_id => x,
+        _ => return Ok(false),
+    };
+
+    let token_domain = format!("access_token:{}", &session.token);
+    let session_domain = format!("session:{}", id);
+    let sessions_domain = format!("sessions:{}", user_id);
+
+    let del_token = store.delete(&token_domain);
+    let del_session = store.delete(&session_domain);
+    let unlist = store.remove_member(&sessions_domain, id.as_bytes());
+
+    let (del_token, (del_session, unlist)) = join(del_token, join(del_session, unlist)).await;
+    del_token.and(del_session).and(unlist)?;
+
+    Ok(true)
+}
+
+/// returns how many sessions were revoked
+pub async fn revoke_all_sessions(
+    store: &web::Data<dyn Store>,
+    user_id: &str,
+) -> Result<usize, StoreError> {
+    let sessions = list_sessions(store, user_id).await?;
+
+    for session in sessions.iter() {
+        revoke_session(store, user_id, &session.id).await?;
+    }
+
+    Ok(sessions.len())
 }
 
 pub fn compose_temp_code_mail(user: &User, email: &str, code: &str) -> Email {
@@ -42,52 +193,187 @@ bye and have a nice day :)
     }
 }
 
-pub async fn check_auth(
-    redis: &web::Data<Addr<RedisActor>>,
+/// who a request acts as. the master key makes you an admin.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Auth {
+    Admin,
+    User(String),
+}
+
+impl Auth {
+    pub fn is_admin(&self) -> bool {
+        *self == Auth::Admin
+    }
+
+    pub fn user_id(&self) -> Option<&str> {
+        match self {
+            Auth::Admin => None,
+            Auth::User(x) => Some(x),
+        }
+    }
+
+    /// may act on behalf of `user_id`
+    pub fn is(&self, user_id: &str) -> bool {
+        self.is_admin() || self.user_id() == Some(user_id)
+    }
+
+    pub fn require_admin(&self) -> Result<(), ApiError> {
+        match self.is_admin() {
+            true => Ok(()),
+            false => Err(ApiError::Forbidden("only admins can do this".into())),
+        }
+    }
+}
+
+/// handlers that take `Auth` answer 401 to requests without a valid token
+impl FromRequest for Auth {
+    type Error = ApiError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let store = req.app_data::<web::Data<dyn Store>>().cloned();
+        let token = bearer_token(req.headers()).map(str::to_string);
+
+        Box::pin(async move {
+            let (store, token) = match (store, token) {
+                (Some(store), Some(token)) => (store, token),
+                _ => return Err(ApiError::Unauthorized),
+            };
+
+            authenticate(&store, &token)
+                .await?
+                .ok_or(ApiError::Unauthorized)
+        })
+    }
+}
+
+// long enough to open the websocket right after asking for it
+pub const TICKET_TTL: usize = 30;
+
+/// a ticket stands in for the access token of `user_id` when opening a
+/// session in `topic_id`. it's put in the url, where logs keep it, so it
+/// works only once and only for a few seconds.
+/// stored under `session_ticket:{topic_id}:{ticket}`
+pub async fn issue_ticket(
+    store: &web::Data<dyn Store>,
+    topic_id: &str,
     user_id: &str,
-    header: &http::header::HeaderMap
-    ) -> Result<bool, Error> {
+) -> Result<String, StoreError> {
+    let ticket = generate_access_token();
+    let ticket_domain = format!("session_ticket:{}:{}", topic_id, &ticket);
+    store
+        .set_expiring(&ticket_domain, user_id.as_bytes(), TICKET_TTL)
+        .await?;
+    Ok(ticket)
+}
+
+/// the user a ticket was issued to, `None` if it's unknown, expired or
+/// already used. using it also spends it
+pub async fn redeem_ticket(
+    store: &web::Data<dyn Store>,
+    topic_id: &str,
+    ticket: &str,
+) -> Result<Option<String>, StoreError> {
+    let ticket_domain = format!("session_ticket:{}:{}", topic_id, ticket);
+
+    let uid = match store.get(&ticket_domain).await? {
+        Some(x) => String::from_utf8(x)
+            .map_err(|_| StoreError::Corrupt(format!("{} is not a user id", ticket_domain)))?,
+        None => return Ok(None),
+    };
+
+    // of two requests with the same ticket only one gets to delete it
+    match store.delete(&ticket_domain).await? {
+        true => Ok(Some(uid)),
+        false => Ok(None),
+    }
+}
 
+/// resolves a bearer token, `None` if it's unknown, expired or revoked
+pub async fn authenticate(
+    store: &web::Data<dyn Store>,
+    token: &str,
+) -> Result<Option<Auth>, StoreError> {
     dotenv().ok();
-    let master_key = std::env::var("MASTER_KEY").unwrap();
+    let master_key = std::env::var("MASTER_KEY").ok();
 
-    let domain = format!("user:{}", user_id);
+    if master_key.as_deref() == Some(token) {
+        return Ok(Some(Auth::Admin))
+    }
 
-    let get = redis.send(Command(resp_array![
-    "GET", 
-    &domain
-    ]));
+    let token_domain = format!("access_token:{}", &token);
 
-    let header = match header.get("Authorization") {
-        Some(h) => h.to_str(),
-        None => {return Ok(false)}
+    let uid: String = match store.get(&token_domain).await? {
+        Some(x) => String::from_utf8(x)
+            .map_err(|_| StoreError::Corrupt(format!("{} is not a user id", token_domain)))?,
+        None => return Ok(None)
+    };
+
+    let get_user = store::get::<User>(&uid, store);
+    // tokens handed out before sessions existed never expire, don't take them
+    let id = session_id(token);
+    let get_session = get_session(store, &id);
+
+    let (user, session) = join(get_user, get_session).await;
+
+    let (user, session) = match (user?, session?) {
+        (Some(user), Some(session)) => (user, session),
+        _ => return Ok(None)
     };
 
-    let bearer = header.unwrap().to_string();
-    let token: &str = match bearer.split_whitespace().nth(1) {
+    if user.id() == uid && session.user_id == uid && session.expires_at > Utc::now().timestamp() {
+        Ok(Some(Auth::User(uid)))
+    } else {
+        Ok(None)
+    }
+}
+
+pub async fn check_auth(
+    store: &web::Data<dyn Store>,
+    user_id: &str,
+    header: &http::header::HeaderMap
+    ) -> Result<bool, ApiError> {
+
+    let token: &str = match bearer_token(header) {
         Some(t) => t,
         None => {return Ok(false)}
     }; 
 
-    if token == master_key{
-        return Ok(true)
+    match authenticate(store, token).await? {
+        Some(auth) => Ok(auth.is(user_id)),
+        None => Ok(false),
     }
+}
 
-    let token_domain = format!("access_token:{}", &token);
-    let get_token = redis.send(Command(resp_array!["GET", &token_domain]));
+pub fn bearer_token(header: &http::header::HeaderMap) -> Option<&str> {
+    header
+        .get("Authorization")?
+        .to_str()
+        .ok()?
+        .split_whitespace()
+        .nth(1)
+}
 
-    let (user, uid) = join(get, get_token).await;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let user: User = match user? {
-        Ok(RespValue::BulkString(x)) => serde_json::from_slice(&x).expect("data shoud be deserializable"),
-        _=> {return Ok(false)}
-    };
+    #[tokio::test]
+    async fn a_ticket_works_once_and_in_its_topic() {
+        let store = crate::store::memory();
+        let ticket = issue_ticket(&store, "topic", "user").await.unwrap();
 
-    let uid: String = match uid? {
-        Ok(RespValue::BulkString(x)) => String::from_utf8(x).unwrap(),
-        _=> return Ok(false)
-    };
-    
-    Ok(user.id() == uid)
-}
+        assert_eq!(redeem_ticket(&store, "other", &ticket).await.unwrap(), None);
+        assert_eq!(redeem_ticket(&store, "topic", &ticket).await.unwrap(), Some("user".to_string()));
+        assert_eq!(redeem_ticket(&store, "topic", &ticket).await.unwrap(), None);
+    }
 
+    #[tokio::test]
+    async fn a_ticket_is_no_access_token() {
+        let store = crate::store::memory();
+        let ticket = issue_ticket(&store, "topic", "user").await.unwrap();
+
+        assert!(authenticate(&store, &ticket).await.unwrap().is_none());
+    }
+}
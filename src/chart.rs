@@ -0,0 +1,89 @@
+// hand written svg, a bar chart is not worth a rendering dependency
+
+const WIDTH: f64 = 640.0;
+const LABEL_WIDTH: f64 = 200.0;
+const VALUE_WIDTH: f64 = 60.0;
+const ROW_HEIGHT: f64 = 28.0;
+const BAR_HEIGHT: f64 = 18.0;
+const TITLE_HEIGHT: f64 = 36.0;
+const MAX_LABEL_LEN: usize = 28;
+
+/// a horizontal bar chart, one row per `(label, value)`, largest first.
+/// bars are scaled to the largest value.
+pub fn bar_chart(title: &str, bars: &[(String, f64)]) -> String {
+    let mut bars: Vec<&(String, f64)> = bars.iter().collect();
+    bars.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    let max = bars
+        .iter()
+        .map(|(_, value)| *value)
+        .fold(0.0, f64::max);
+    let bar_space = WIDTH - LABEL_WIDTH - VALUE_WIDTH;
+    let height = TITLE_HEIGHT + ROW_HEIGHT * bars.len().max(1) as f64 + 8.0;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" font-family=\"sans-serif\" font-size=\"13\">\n",
+        w = WIDTH,
+        h = height
+    );
+    svg.push_str(&format!(
+        "  <rect width=\"{}\" height=\"{}\" fill=\"#ffffff\"/>\n",
+        WIDTH, height
+    ));
+    svg.push_str(&format!(
+        "  <text x=\"8\" y=\"22\" font-size=\"16\" font-weight=\"bold\">{}</text>\n",
+        escape(title)
+    ));
+
+    if bars.is_empty() {
+        svg.push_str(&format!(
+            "  <text x=\"8\" y=\"{}\" fill=\"#888888\">nothing yet</text>\n",
+            TITLE_HEIGHT + BAR_HEIGHT
+        ));
+    }
+
+    for (i, (label, value)) in bars.iter().enumerate() {
+        let y = TITLE_HEIGHT + ROW_HEIGHT * i as f64;
+        let length = match max > 0.0 {
+            true => bar_space * value.max(0.0) / max,
+            false => 0.0,
+        };
+
+        svg.push_str(&format!(
+            "  <text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>\n",
+            LABEL_WIDTH - 8.0,
+            y + BAR_HEIGHT - 4.0,
+            escape(&truncate(label))
+        ));
+        svg.push_str(&format!(
+            "  <rect x=\"{}\" y=\"{}\" width=\"{:.1}\" height=\"{}\" fill=\"#4a7bd0\"/>\n",
+            LABEL_WIDTH,
+            y,
+            length,
+            BAR_HEIGHT
+        ));
+        svg.push_str(&format!(
+            "  <text x=\"{:.1}\" y=\"{}\">{:.3}</text>\n",
+            LABEL_WIDTH + length + 6.0,
+            y + BAR_HEIGHT - 4.0,
+            value
+        ));
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn truncate(label: &str) -> String {
+    match label.chars().count() > MAX_LABEL_LEN {
+        true => format!("{}…", label.chars().take(MAX_LABEL_LEN - 1).collect::<String>()),
+        false => label.to_string(),
+    }
+}
+
+fn escape(x: &str) -> String {
+    x.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
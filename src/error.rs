@@ -0,0 +1,127 @@
+use crate::model::{FieldError, PlanError, VoteError};
+use crate::store::StoreError;
+use actix_web::{error::JsonPayloadError, http::StatusCode, HttpRequest, HttpResponse, ResponseError};
+use serde::Serialize;
+
+/// every handler answers failures with this, rendered as
+/// `{"code": "not_found", "message": "..."}`. the codes are stable,
+/// clients should match on them rather than on the message.
+/// `invalid_plan` and `invalid_vote` also list the offending fields
+/// under `fields`.
+#[derive(Debug)]
+pub enum ApiError {
+    NotFound(String),
+    InvalidRequest(String),
+    InvalidPlan(PlanError),
+    InvalidVote(VoteError),
+    Unauthorized,
+    Forbidden(String),
+    Storage(StoreError),
+    Conflict(String),
+    Internal(String),
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'a str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<&'a [FieldError]>,
+}
+
+impl ApiError {
+    pub fn not_found(domain_prefix: &str, id: &str) -> Self {
+        ApiError::NotFound(format!("{}:{} does not exist", domain_prefix, id))
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) => "not_found",
+            ApiError::InvalidRequest(_) => "invalid_request",
+            ApiError::InvalidPlan(_) => "invalid_plan",
+            ApiError::InvalidVote(_) => "invalid_vote",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::Storage(_) => "storage_error",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// what the response carries, also for errors that don't go out as one
+    pub fn body(&self) -> serde_json::Value {
+        let fields = match self {
+            ApiError::InvalidPlan(e) => Some(e.0.as_slice()),
+            ApiError::InvalidVote(e) => Some(e.0.as_slice()),
+            _ => None,
+        };
+
+        serde_json::to_value(ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+            fields,
+        })
+        .expect("ErrorBody should be Serializable")
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::NotFound(x)
+            | ApiError::InvalidRequest(x)
+            | ApiError::Forbidden(x)
+            | ApiError::Conflict(x)
+            | ApiError::Internal(x) => write!(f, "{}", x),
+            ApiError::InvalidPlan(x) => write!(f, "{}", x),
+            ApiError::InvalidVote(x) => write!(f, "{}", x),
+            ApiError::Unauthorized => write!(f, "missing or invalid access token"),
+            ApiError::Storage(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::InvalidRequest(_) | ApiError::InvalidPlan(_) | ApiError::InvalidVote(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::Storage(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(self.body())
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(e: StoreError) -> Self {
+        match e {
+            StoreError::Conflict(x) => ApiError::Conflict(x),
+            e => ApiError::Storage(e),
+        }
+    }
+}
+
+impl From<PlanError> for ApiError {
+    fn from(e: PlanError) -> Self {
+        ApiError::InvalidPlan(e)
+    }
+}
+
+impl From<VoteError> for ApiError {
+    fn from(e: VoteError) -> Self {
+        ApiError::InvalidVote(e)
+    }
+}
+
+/// malformed json bodies get the same error shape as everything else
+pub fn json_error(e: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::InvalidRequest(e.to_string()).into()
+}
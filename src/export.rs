@@ -0,0 +1,133 @@
+use simple_excel_writer::{Row, Workbook};
+
+pub enum Cell {
+    Text(String),
+    Number(f64),
+    Empty,
+}
+
+/// one sheet of an export, or one section of a csv
+pub struct Table {
+    pub name: &'static str,
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<Cell>>,
+}
+
+/// every table below the one before it, separated by an empty line
+pub fn to_csv(tables: &[Table]) -> String {
+    let sections: Vec<String> = tables
+        .iter()
+        .map(|table| {
+            let mut lines = vec![csv_line(table.header.iter().map(|x| csv_text(x)))];
+
+            for row in table.rows.iter() {
+                lines.push(csv_line(row.iter().map(|cell| match cell {
+                    Cell::Text(x) => csv_text(x),
+                    Cell::Number(x) => x.to_string(),
+                    Cell::Empty => String::new(),
+                })));
+            }
+
+            lines.join("\r\n")
+        })
+        .collect();
+
+    let mut csv = sections.join("\r\n\r\n");
+    csv.push_str("\r\n");
+    csv
+}
+
+fn csv_line(fields: impl Iterator<Item = String>) -> String {
+    fields.collect::<Vec<String>>().join(",")
+}
+
+// nicknames and titles come from users. quote them, and don't let a
+// spreadsheet take them for formulas
+fn csv_text(x: &str) -> String {
+    let x = match x.starts_with(&['=', '+', '-', '@', '\t', '\r'][..]) {
+        true => format!("'{}", x),
+        false => x.to_string(),
+    };
+
+    match x.contains(&[',', '"', '\n', '\r'][..]) {
+        true => format!("\"{}\"", x.replace('"', "\"\"")),
+        false => x,
+    }
+}
+
+/// one sheet per table
+pub fn to_xlsx(tables: &[Table]) -> std::io::Result<Vec<u8>> {
+    let mut workbook = Workbook::create_in_memory();
+
+    for table in tables.iter() {
+        let mut sheet = workbook.create_sheet(table.name);
+
+        workbook.write_sheet(&mut sheet, |writer| {
+            let mut header = Row::new();
+            for x in table.header.iter() {
+                header.add_cell(x.as_str());
+            }
+            writer.append_row(header)?;
+
+            for cells in table.rows.iter() {
+                let mut row = Row::new();
+                for cell in cells.iter() {
+                    match cell {
+                        Cell::Text(x) => row.add_cell(x.as_str()),
+                        Cell::Number(x) => row.add_cell(*x),
+                        Cell::Empty => row.add_cell(()),
+                    }
+                }
+                writer.append_row(row)?;
+            }
+
+            Ok(())
+        })?;
+    }
+
+    Ok(workbook
+        .close()?
+        .expect("an in-memory workbook closes to its bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formulas_are_defused() {
+        for x in ["=1+1", "+1", "-1", "@SUM(A1)", "\tx"].iter() {
+            assert_eq!(csv_text(x), format!("'{}", x));
+        }
+        assert_eq!(csv_text("1-1"), "1-1");
+    }
+
+    #[test]
+    fn quotes_are_doubled() {
+        assert_eq!(csv_text("a,b"), "\"a,b\"");
+        assert_eq!(csv_text("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_text("=a,b"), "\"'=a,b\"");
+        assert_eq!(csv_text("\r\n"), "\"'\r\n\"");
+    }
+
+    #[test]
+    fn tables_follow_each_other() {
+        let tables = [
+            Table {
+                name: "a",
+                header: vec!["name".to_string(), "votes".to_string()],
+                rows: vec![
+                    vec![Cell::Text("=x".to_string()), Cell::Number(0.5)],
+                    vec![Cell::Text("y".to_string()), Cell::Empty],
+                ],
+            },
+            Table {
+                name: "b",
+                header: vec!["id".to_string()],
+                rows: vec![],
+            },
+        ];
+
+        assert_eq!(to_csv(&tables), "name,votes\r\n'=x,0.5\r\ny,\r\n\r\nid\r\n");
+    }
+}
@@ -1,104 +1,107 @@
 pub mod topic;
 pub mod user;
 pub mod plan;
+pub mod result;
+pub mod session;
+pub mod stream;
+pub mod webhook;
 
 use crate::{
-    model::{Topic, User, Plan, RawPlan},
-    redis_helper::{redis_add, redis_get_list, redis_get_slices},
+    auth::Auth,
+    error::ApiError,
+    model::{Topic, User, Plan, RawPlan, SettingDiff, SettingLimits, SettingView},
+    results::ResultCache,
+    store::{self, Store},
 };
-use actix::Addr;
-use actix_redis::{Command, RedisActor};
-use actix_web::{web, Error as AWError, HttpResponse, Responder};
+use actix_web::{web, HttpResponse};
 use futures::future::{join, join_all};
 use liq::Setting;
-use redis_async::{resp::RespValue, resp_array};
 use serde::{Serialize, Deserialize};
+use std::convert::TryInto;
 
-pub async fn nuclear(redis: web::Data<Addr<RedisActor>>) -> Result<impl Responder, AWError> {
-    let res = redis.send(Command(resp_array!["FLUSHALL",])).await?;
-
-    match res {
-        Ok(RespValue::SimpleString(x)) if x == "OK" => Ok("ok"),
-        _ => Ok("nope"),
-    }
+pub async fn nuclear(store: web::Data<dyn Store>, auth: Auth) -> Result<HttpResponse, ApiError> {
+    auth.require_admin()?;
+    store.flush().await?;
+    Ok(HttpResponse::Ok().body("ok"))
 }
 
 pub async fn get_setting(
-    redis: web::Data<Addr<RedisActor>>,
+    store: web::Data<dyn Store>,
     setting_id: web::Path<String>,
-) -> Result<HttpResponse, AWError> {
+) -> Result<HttpResponse, ApiError> {
     let setting_id = setting_id.into_inner();
-    let domain = format!("setting:{}", setting_id);
 
-    let res = redis.send(Command(resp_array!["get", &domain])).await?;
+    let res = store::get_slice(&setting_id, "setting", &store).await?;
 
     match res {
-        Ok(RespValue::BulkString(x)) => Ok(HttpResponse::Ok().body(x)),
-        _ => Ok(HttpResponse::NoContent().finish()),
+        Some(x) => Ok(HttpResponse::Ok().content_type("application/json").body(x)),
+        None => Err(ApiError::not_found("setting", &setting_id)),
     }
 }
 
-pub async fn calculate_setting(setting: web::Json<Setting>) -> Result<HttpResponse, AWError> {
+/// how two stored settings differ, `from` doesn't have to come before `to`
+pub async fn diff_settings(
+    store: web::Data<dyn Store>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (from_hash, to_hash) = path.into_inner();
+
+    let (from, to) = join(
+        store::get::<Setting>(&from_hash, &store),
+        store::get::<Setting>(&to_hash, &store),
+    )
+    .await;
+
+    let from = from?.ok_or_else(|| ApiError::not_found("setting", &from_hash))?;
+    let to = to?.ok_or_else(|| ApiError::not_found("setting", &to_hash))?;
+
+    Ok(HttpResponse::Ok().json(SettingDiff::between(&from_hash, &from, &to_hash, &to)))
+}
+
+/// anyone's setting, calculated but never stored: it's only the topics'
+/// results that are worth keeping
+pub async fn calculate_setting(
+    results: web::Data<ResultCache>,
+    setting: web::Json<Setting>,
+) -> Result<HttpResponse, ApiError> {
     let setting = setting.into_inner();
-    let result = setting.calculate();
+
+    SettingLimits::from_env()
+        .check(&SettingView::from(&setting))
+        .map_err(ApiError::InvalidRequest)?;
+
+    let result = results.calculate(&setting);
 
     Ok(HttpResponse::Ok().json(result))
 }
 
-pub async fn dump(redis: web::Data<Addr<RedisActor>>) -> Result<HttpResponse, AWError> {
+pub async fn dump(store: web::Data<dyn Store>, auth: Auth) -> Result<HttpResponse, ApiError> {
+    auth.require_admin()?;
+
     // get all tables
 
-    let topics_cmd = redis_get_list("topic", &redis);
-    let users_cmd = redis_get_list("user", &redis);
-    let plan_cmd = redis_get_list("plan", &redis);
+    let topics_cmd = store::get_list("topic", &store);
+    let users_cmd = store::get_list("user", &store);
+    let plan_cmd = store::get_list("plan", &store);
 
     let (topic_list_items, (user_list_items, plan_list_items)) = join(topics_cmd, join(users_cmd, plan_cmd)).await;
 
-    let topic_ids: Vec<String> = match topic_list_items{
-        Some(x) => x.iter().map(|li| li.0.to_string()).collect(),
-        None => {
-            return Ok(HttpResponse::InternalServerError().body("failed to get topic_ids"))
-    }};
-        
-    let user_ids: Vec<String> = match user_list_items{
-        Some(x) => x.iter().map(|li| li.0.to_string()).collect(),
-        None => {
-            return Ok(HttpResponse::InternalServerError().body("failed to get user_ids"))
-    }};
-
-    let plan_ids: Vec<String> = match plan_list_items{
-        Some(x) => x.iter().map(|li| li.0.to_string()).collect(),
-        None => {
-            return Ok(HttpResponse::InternalServerError().body("failed to get user_ids"))
-    }};
-
-    let topics_cmd = redis_get_slices(&topic_ids, "topic", &redis);
-    let users_cmd = redis_get_slices(&user_ids, "user", &redis);
-    let plan_cmd = redis_get_slices(&plan_ids, "plan", &redis);
-
-    let (topic_slices, (user_slices, plan_slices)) = join(topics_cmd, join(users_cmd, plan_cmd)).await;
-
-    let topics: Vec<Topic> = topic_slices
-        .iter()
-        .map(|t| serde_json::from_slice(t)
-            .expect("this slice should be Deserialized to Topic"))
-        .collect();
+    let topic_ids: Vec<String> = topic_list_items?.into_iter().map(|li| li.0).collect();
+    let user_ids: Vec<String> = user_list_items?.into_iter().map(|li| li.0).collect();
+    let plan_ids: Vec<String> = plan_list_items?.into_iter().map(|li| li.0).collect();
 
-    let users: Vec<User> = user_slices
-        .iter()
-        .map(|u| serde_json::from_slice(u)
-            .expect("this slice should be Deserialized to User"))
-        .collect();
+    let topics_cmd = store::get_all::<Topic>(&topic_ids, "topic", &store);
+    let users_cmd = store::get_all::<User>(&user_ids, "user", &store);
+    let plan_cmd = store::get_all::<RawPlan>(&plan_ids, "plan", &store);
 
-    let mut plans: Vec<RawPlanWrapper> = Vec::new();
+    let (topics, (users, plans)) = join(topics_cmd, join(users_cmd, plan_cmd)).await;
 
-    for (i, slice) in plan_slices.iter().enumerate() {
-        let id: String = plan_ids[i].to_string();
-        let raw: RawPlan = serde_json::from_slice(&slice)
-            .expect("slice should be Deserialized to RawPlan");
-        let wrapper = RawPlanWrapper{id, raw};
-       plans.push(wrapper) 
-    }
+    let topics: Vec<Topic> = topics?.into_iter().map(|(_, t)| t).collect();
+    let users: Vec<User> = users?.into_iter().map(|(_, u)| u).collect();
+    let plans: Vec<RawPlanWrapper> = plans?
+        .into_iter()
+        .map(|(id, raw)| RawPlanWrapper{id, raw})
+        .collect();
 
     Ok(HttpResponse::Ok().json((users, topics, plans)))
 }
@@ -112,20 +115,32 @@ pub struct RawPlanWrapper {
 type DumpFile = (Vec<User>, Vec<Topic>, Vec<RawPlanWrapper>);
 
 pub async fn restore(
-    redis: web::Data<Addr<RedisActor>>,
+    store: web::Data<dyn Store>,
     dump: web::Json<DumpFile>,
-) -> Result<HttpResponse, AWError> {
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
+    auth.require_admin()?;
+
     let (users, topics, plans) = dump.into_inner();
 
-    let user_add = join_all(users.into_iter().map(|u| redis_add(u, &redis)));
-    let topic_add = join_all(topics.into_iter().map(|t| redis_add(t, &redis)));
+    // check every plan before writing anything
+    let plans: Vec<Plan> = plans
+        .into_iter()
+        .map(|w| w.raw.try_into())
+        .collect::<Result<_, _>>()?;
+
+    let user_add = join_all(users.into_iter().map(|u| store::add(u, &store)));
+    let plan_add = join_all(plans.into_iter().map(|p| store::add(p, &store)));
 
-    let plan_add = join_all(plans.into_iter().map(|w| {
-        let plan: Plan = w.raw.into();
-        redis_add(plan, &redis)
-    }));
+    // topics refer to users and plans, so they go in last
+    let (users_added, plans_added) = join(user_add, plan_add).await;
+    let topics_added = join_all(topics.into_iter().map(|t| store::add(t, &store))).await;
 
-    let _all = join(user_add, join(topic_add, plan_add)).await;
+    users_added
+        .into_iter()
+        .chain(plans_added)
+        .chain(topics_added)
+        .collect::<Result<Vec<()>, _>>()?;
 
     Ok(HttpResponse::Ok().body("success"))
 }
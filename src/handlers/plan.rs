@@ -1,39 +1,36 @@
 use crate::{
+    auth::Auth,
+    error::ApiError,
     model::{RawPlan, Plan, Settable},
-    redis_helper::{redis_add, redis_get_slice},
+    store::{self, Store},
 };
-use actix::prelude::*;
-use actix_redis::RedisActor;
-use actix_web::{web, Error as AWError, HttpResponse};
+use actix_web::{web, HttpResponse};
+use std::convert::TryInto;
 
 pub async fn get(
-    redis: web::Data<Addr<RedisActor>>,
+    store: web::Data<dyn Store>,
     plan_id: web::Path<String>,
-) -> Result<HttpResponse, AWError> {
+) -> Result<HttpResponse, ApiError> {
     let plan_id = plan_id.into_inner();
 
-    let slice = redis_get_slice(&plan_id, "plan", &redis).await;
+    let plan: Plan = store::get(&plan_id, &store)
+        .await?
+        .ok_or_else(|| ApiError::not_found("plan", &plan_id))?;
 
-    match slice {
-        Some(x) => {
-            let plan: Plan =
-                serde_json::from_slice(&x).expect("this slice should be able to Deserilaze");
-            Ok(HttpResponse::Ok().json(&plan))
-        }
-        None => Ok(HttpResponse::NoContent().finish()),
-    }
+    Ok(HttpResponse::Ok().json(&plan))
 }
 
 pub async fn put(
-    redis: web::Data<Addr<RedisActor>>,
+    store: web::Data<dyn Store>,
     raw_plan: web::Json<RawPlan>,
-) -> Result<HttpResponse, AWError> {
-    let plan: Plan = raw_plan.into_inner().into();
+    _auth: Auth,
+) -> Result<HttpResponse, ApiError> {
+    let plan: Plan = raw_plan.into_inner().try_into()?;
     let id = plan.id();
-    match redis_add(plan, &redis).await {
-        true => Ok(HttpResponse::Ok().json(id)),
-        false => Ok(HttpResponse::InternalServerError().body("could not put plan")),
-    }
+
+    store::add(plan, &store).await?;
+
+    Ok(HttpResponse::Ok().json(id))
 }
 
 
@@ -0,0 +1,259 @@
+use crate::{
+    chart,
+    error::ApiError,
+    export::{self, Cell, Table},
+    model::{Plan, Topic, User},
+    store::{self, Store},
+};
+use actix_web::{web, HttpResponse};
+use bs58::encode;
+use futures::future::join;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+// charts only depend on the setting and the labels, but don't keep them
+// around forever
+const CHART_TTL: usize = 60 * 60 * 24;
+
+/// plan titles and voter nicknames by id. ids that can't be resolved
+/// stand for themselves.
+#[derive(Serialize)]
+pub struct Labels {
+    plans: BTreeMap<String, String>,
+    users: BTreeMap<String, String>,
+}
+
+impl Labels {
+    pub async fn of(topic: &Topic, store: &web::Data<dyn Store>) -> Result<Self, ApiError> {
+        let plan_ids = topic.plan_ids();
+        let voter_ids = topic.voter_ids();
+
+        let (plans, users) = join(
+            store::get_all::<Plan>(&plan_ids, "plan", store),
+            store::get_all::<User>(&voter_ids, "user", store),
+        )
+        .await;
+
+        Ok(Self {
+            plans: plans?
+                .into_iter()
+                .map(|(id, plan)| (id, plan.label()))
+                .collect(),
+            users: users?
+                .into_iter()
+                .map(|(id, user)| (id, user.nickname))
+                .collect(),
+        })
+    }
+
+    pub fn plan(&self, id: &str) -> String {
+        self.plans.get(id).cloned().unwrap_or_else(|| id.to_string())
+    }
+
+    pub fn user(&self, id: &str) -> String {
+        self.users.get(id).cloned().unwrap_or_else(|| id.to_string())
+    }
+
+    /// changes whenever a title or nickname does
+    pub fn digest(&self) -> String {
+        let json = serde_json::to_vec(self).expect("Labels should be Serializable");
+        encode(Sha256::digest(&json)).into_string()
+    }
+}
+
+/// `votes` per plan or `influence` per voter as an svg bar chart
+pub async fn chart(
+    store: web::Data<dyn Store>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (topic_id, kind) = path.into_inner();
+
+    if kind != "votes" && kind != "influence" {
+        return Err(ApiError::InvalidRequest(format!(
+            "unknown chart '{}', expected votes or influence",
+            kind
+        )));
+    }
+
+    let topic: Topic = store::get(&topic_id, &store)
+        .await?
+        .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
+
+    // same setting and labels, same chart. a renamed plan or voter makes
+    // a new one, the old one expires
+    let labels = Labels::of(&topic, &store).await?;
+    let version = format!("{}-{}-{}", topic.setting_hash, kind, labels.digest());
+    let cache_domain = format!("chart:{}:{}", topic_id, version);
+
+    let svg = match store.get(&cache_domain).await? {
+        Some(svg) => svg,
+        None => {
+            let result = topic.result_view();
+
+            let svg = match kind.as_str() {
+                "votes" => {
+                    let bars: Vec<(String, f64)> = result
+                        .votes
+                        .iter()
+                        .map(|(id, x)| (labels.plan(id), *x))
+                        .collect();
+                    chart::bar_chart("votes", &bars)
+                }
+                _ => {
+                    let bars: Vec<(String, f64)> = result
+                        .influence
+                        .iter()
+                        .map(|(id, x)| (labels.user(id), *x))
+                        .collect();
+                    chart::bar_chart("influence", &bars)
+                }
+            };
+
+            store
+                .set_expiring(&cache_domain, svg.as_bytes(), CHART_TTL)
+                .await?;
+            svg.into_bytes()
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("image/svg+xml")
+        .header("ETag", format!("\"{}\"", version))
+        .body(svg))
+}
+
+fn tables(topic: &Topic, labels: &Labels) -> Vec<Table> {
+    let setting = topic.setting_view();
+    let result = topic.result_view();
+
+    let votes = Table {
+        name: "votes",
+        header: vec!["plan_id".into(), "plan".into(), "votes".into()],
+        rows: setting
+            .plans
+            .iter()
+            .map(|id| {
+                vec![
+                    Cell::Text(id.to_owned()),
+                    Cell::Text(labels.plan(id)),
+                    Cell::Number(result.votes.get(id).copied().unwrap_or(0.0)),
+                ]
+            })
+            .collect(),
+    };
+
+    let influence = Table {
+        name: "influence",
+        header: vec!["voter_id".into(), "voter".into(), "influence".into()],
+        rows: setting
+            .voters
+            .iter()
+            .map(|id| {
+                vec![
+                    Cell::Text(id.to_owned()),
+                    Cell::Text(labels.user(id)),
+                    Cell::Number(result.influence.get(id).copied().unwrap_or(0.0)),
+                ]
+            })
+            .collect(),
+    };
+
+    // a column per plan, then one per voter that can be delegated to
+    let columns: Vec<&String> = setting.plans.iter().chain(setting.voters.iter()).collect();
+
+    let mut header: Vec<String> = vec!["voter_id".into(), "voter".into()];
+    header.extend(setting.plans.iter().map(|id| labels.plan(id)));
+    header.extend(setting.voters.iter().map(|id| labels.user(id)));
+
+    let ballots = Table {
+        name: "ballots",
+        header,
+        rows: setting
+            .voters
+            .iter()
+            .map(|voter| {
+                let vote = setting.votes.get(voter);
+                let mut row = vec![Cell::Text(voter.to_owned()), Cell::Text(labels.user(voter))];
+                row.extend(columns.iter().map(|column| {
+                    match vote.and_then(|x| x.get(*column)) {
+                        Some(weight) => Cell::Number(*weight),
+                        None => Cell::Empty,
+                    }
+                }));
+                row
+            })
+            .collect(),
+    };
+
+    vec![votes, influence, ballots]
+}
+
+/// `result.csv` or `result.xlsx`: votes per plan, influence per voter and
+/// every ballot. the csv has them one after the other, the xlsx a sheet each
+pub async fn export(
+    store: web::Data<dyn Store>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (topic_id, format) = path.into_inner();
+
+    let topic: Topic = store::get(&topic_id, &store)
+        .await?
+        .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
+
+    let labels = Labels::of(&topic, &store).await?;
+    let tables = tables(&topic, &labels);
+
+    let (content_type, body) = match format.as_str() {
+        "csv" => ("text/csv; charset=utf-8", export::to_csv(&tables).into_bytes()),
+        "xlsx" => (
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            export::to_xlsx(&tables).map_err(|e| ApiError::Internal(e.to_string()))?,
+        ),
+        x => {
+            return Err(ApiError::InvalidRequest(format!(
+                "unknown format '{}', expected csv or xlsx",
+                x
+            )))
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}.{}\"", topic_id, format),
+        )
+        .body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{PartialTopic, Settable};
+
+    fn etag(response: &HttpResponse) -> String {
+        response.headers().get("ETag").unwrap().to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn a_new_label_makes_a_new_chart() {
+        let store = store::memory();
+        let plan = Plan::Simple("bread".into());
+
+        let partial: PartialTopic =
+            serde_json::from_value(serde_json::json!({"title": "t", "description": "d"})).unwrap();
+        let mut topic: Topic = partial.into();
+        topic.add_plan_id(&plan.id());
+        let topic_id = topic.id();
+        store::add(topic, &store).await.unwrap();
+
+        let path = || web::Path::from((topic_id.to_owned(), "votes".to_string()));
+        let unnamed = chart(store.clone(), path()).await.unwrap();
+        assert_eq!(etag(&unnamed), etag(&chart(store.clone(), path()).await.unwrap()));
+
+        store::add(plan, &store).await.unwrap();
+        let named = chart(store.clone(), path()).await.unwrap();
+        assert_ne!(etag(&unnamed), etag(&named));
+    }
+}
@@ -0,0 +1,226 @@
+use crate::{
+    auth::{authenticate, bearer_token, issue_ticket, redeem_ticket, Auth, TICKET_TTL},
+    error::ApiError,
+    handlers::topic::cast_vote,
+    live::{Live, Push, CLIENT_BUFFER},
+    model::{Topic, Vote},
+    results::ResultCache,
+    store::{self, Store},
+};
+use actix::{fut, Actor, ActorContext, ActorFuture, AsyncContext, Handler, StreamHandler};
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use serde::Deserialize;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+const PING_INTERVAL: Duration = Duration::from_secs(10);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize)]
+pub struct SessionQuery {
+    /// browsers can't set headers on a websocket, they bring a ticket
+    ticket: Option<String>,
+}
+
+/// a ticket to open a session in the topic with, for clients that can't
+/// send the access token along. see `auth::issue_ticket`
+pub async fn ticket(
+    store: web::Data<dyn Store>,
+    topic_id: web::Path<String>,
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
+    let topic_id = topic_id.into_inner();
+    let user_id = seated_user(auth)?;
+
+    let topic: Topic = store::get(&topic_id, &store)
+        .await?
+        .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
+    require_role(&topic, &user_id)?;
+
+    let ticket = issue_ticket(&store, &topic_id, &user_id).await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "ticket": ticket, "expires_in": TICKET_TTL })))
+}
+
+// presence is per user, an admin is nobody in particular
+fn seated_user(auth: Auth) -> Result<String, ApiError> {
+    match auth {
+        Auth::User(x) => Ok(x),
+        Auth::Admin => Err(ApiError::Forbidden("admins can follow the events instead".into())),
+    }
+}
+
+fn require_role(topic: &Topic, user_id: &str) -> Result<(), ApiError> {
+    match topic.role_of(user_id) {
+        Some(_) => Ok(()),
+        None => Err(ApiError::Forbidden(format!("{} has no role in this topic", user_id))),
+    }
+}
+
+/// what a client sends. `id` is echoed in the ack, so it can tell which
+/// of its messages went through
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Incoming {
+    Vote {
+        #[serde(default)]
+        id: Option<String>,
+        vote: Vote,
+    },
+}
+
+/// a websocket for voting in person. whoever holds a role in the topic may
+/// join and gets its result and who else is there, voters can vote too.
+/// every message is answered with an ack. takes the access token as a
+/// bearer token or a `ticket` from `ticket`.
+pub async fn join(
+    req: HttpRequest,
+    stream: web::Payload,
+    store: web::Data<dyn Store>,
+    live: web::Data<Live>,
+    results: web::Data<ResultCache>,
+    topic_id: web::Path<String>,
+    query: web::Query<SessionQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let topic_id = topic_id.into_inner();
+
+    let user_id = match (bearer_token(req.headers()), query.into_inner().ticket) {
+        (Some(token), _) => authenticate(&store, token)
+            .await
+            .map_err(ApiError::from)?
+            .map(seated_user)
+            .transpose()?,
+        (None, Some(ticket)) => redeem_ticket(&store, &topic_id, &ticket)
+            .await
+            .map_err(ApiError::from)?,
+        (None, None) => None,
+    }
+    .ok_or(ApiError::Unauthorized)?;
+
+    let topic: Topic = store::get(&topic_id, &store)
+        .await
+        .map_err(ApiError::from)?
+        .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
+
+    // a role can be gone since the ticket was issued
+    require_role(&topic, &user_id)?;
+
+    let session = VotingSession {
+        topic_id,
+        joining: Some(topic),
+        user_id,
+        store,
+        live,
+        results,
+        seat: None,
+        last_seen: Instant::now(),
+    };
+
+    ws::start(session, &req, stream)
+}
+
+pub struct VotingSession {
+    topic_id: String,
+    // the topic as it was when joining, until the session is seated
+    joining: Option<Topic>,
+    user_id: String,
+    store: web::Data<dyn Store>,
+    live: web::Data<Live>,
+    results: web::Data<ResultCache>,
+    seat: Option<u64>,
+    last_seen: Instant,
+}
+
+impl VotingSession {
+    fn vote(&mut self, id: Option<String>, vote: Vote, ctx: &mut ws::WebsocketContext<Self>) {
+        let store = self.store.clone();
+        let live = self.live.clone();
+        let results = self.results.clone();
+        let topic_id = self.topic_id.to_owned();
+        let user_id = self.user_id.to_owned();
+        let auth = Auth::User(self.user_id.to_owned());
+
+        let cast = async move { cast_vote(&store, &live, &results, &topic_id, &user_id, vote, &auth).await };
+
+        // the new result reaches everyone through `Live`, the ack only
+        // says how it went
+        ctx.spawn(fut::wrap_future::<_, Self>(cast).map(move |result, _, ctx| {
+            let ack = match result {
+                Ok(topic) => json!({ "type": "ack", "id": id, "ok": true, "changed": topic.is_some() }),
+                Err(e) => json!({ "type": "ack", "id": id, "ok": false, "error": e.body() }),
+            };
+            ctx.text(ack.to_string());
+        }));
+    }
+}
+
+impl Actor for VotingSession {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.set_mailbox_capacity(CLIENT_BUFFER);
+        if let Some(topic) = self.joining.take() {
+            self.seat = Some(self.live.join(&topic, &self.user_id, ctx.address().recipient()));
+        }
+
+        // a client that stops answering pings is gone, one that fell
+        // behind lost its seat
+        ctx.run_interval(PING_INTERVAL, |session, ctx| {
+            let seated = match session.seat {
+                Some(seat) => session.live.seated(&session.topic_id, seat),
+                None => true,
+            };
+            if !seated || Instant::now().duration_since(session.last_seen) > CLIENT_TIMEOUT {
+                ctx.stop();
+                return;
+            }
+            ctx.ping(b"");
+        });
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        if let Some(seat) = self.seat.take() {
+            self.live.leave(&self.topic_id, seat);
+        }
+    }
+}
+
+impl Handler<Push> for VotingSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: Push, ctx: &mut Self::Context) {
+        ctx.text(msg.0);
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for VotingSession {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        let msg = match msg {
+            Ok(x) => x,
+            Err(_) => {
+                ctx.stop();
+                return;
+            }
+        };
+
+        self.last_seen = Instant::now();
+
+        match msg {
+            ws::Message::Text(text) => match serde_json::from_str::<Incoming>(&text) {
+                Ok(Incoming::Vote { id, vote }) => self.vote(id, vote, ctx),
+                Err(e) => {
+                    let e = ApiError::InvalidRequest(e.to_string());
+                    let ack = json!({ "type": "ack", "id": null, "ok": false, "error": e.body() });
+                    ctx.text(ack.to_string());
+                }
+            },
+            ws::Message::Ping(x) => ctx.pong(&x),
+            ws::Message::Close(reason) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            _ => (),
+        }
+    }
+}
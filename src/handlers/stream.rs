@@ -0,0 +1,134 @@
+use crate::{
+    auth::Auth,
+    error::ApiError,
+    model::{DomainEvent, EventData},
+    store::{Store, StoreError},
+};
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+/// every change made through the api ends up here, in order
+pub(crate) const EVENT_STREAM: &str = "events";
+
+const DEFAULT_COUNT: usize = 10;
+const MAX_COUNT: usize = 100;
+
+/// appends to the event stream. the change already happened by the time
+/// this runs, so failing to record it is logged rather than answered with
+pub async fn emit(store: &web::Data<dyn Store>, by: Option<&str>, data: EventData) {
+    let event = DomainEvent::now(by, data);
+    let json = serde_json::to_vec(&event).expect("DomainEvent should be Serializable");
+
+    if let Err(e) = store.append(EVENT_STREAM, &json).await {
+        log::error!("could not append {:?} to the event stream: {}", event, e);
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ReadQuery {
+    consumer: String,
+    count: Option<usize>,
+    /// what `consumer` was handed before and didn't ack
+    #[serde(default)]
+    pending: bool,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    id: String,
+    /// a `DomainEvent`, passed on as it was written so consumers also
+    /// see types newer than this server
+    event: serde_json::Value,
+}
+
+/// a consumer group reads every event once, no matter how many consumers
+/// share the work. it starts at the oldest event still kept
+pub async fn create_group(
+    store: web::Data<dyn Store>,
+    group: web::Path<String>,
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
+    auth.require_admin()?;
+
+    store.create_group(EVENT_STREAM, &group).await?;
+
+    Ok(HttpResponse::Ok().json(group.into_inner()))
+}
+
+/// the next events for `consumer`. they stay pending until acked
+pub async fn read(
+    store: web::Data<dyn Store>,
+    group: web::Path<String>,
+    query: web::Query<ReadQuery>,
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
+    auth.require_admin()?;
+
+    let count = query.count.unwrap_or(DEFAULT_COUNT).clamp(1, MAX_COUNT);
+
+    let read = store
+        .read_group(EVENT_STREAM, &group, &query.consumer, count, query.pending)
+        .await;
+
+    let entries = match read {
+        Err(StoreError::Backend(e)) if e.starts_with("NOGROUP") => {
+            return Err(ApiError::NotFound(format!("no consumer group {}", group)))
+        }
+        x => x?,
+    };
+
+    let entries: Vec<Entry> = entries
+        .into_iter()
+        .map(|(id, x)| {
+            serde_json::from_slice(&x)
+                .map(|event| Entry { id: id.to_owned(), event })
+                .map_err(|_| ApiError::Internal(format!("event {} is not json", id)))
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+// `1234` from memory and sqlite, `1234-0` from redis. redis refuses the
+// whole ack for anything else, the others would skip it
+fn is_entry_id(id: &str) -> bool {
+    let digits = |x: &str| !x.is_empty() && x.bytes().all(|b| b.is_ascii_digit());
+
+    match id.split_once('-') {
+        Some((time, seq)) => digits(time) && digits(seq),
+        None => digits(id),
+    }
+}
+
+/// answers how many of the ids were pending
+pub async fn ack(
+    store: web::Data<dyn Store>,
+    group: web::Path<String>,
+    ids: web::Json<Vec<String>>,
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
+    auth.require_admin()?;
+
+    if let Some(id) = ids.iter().find(|x| !is_entry_id(x)) {
+        return Err(ApiError::InvalidRequest(format!("{} is not an event id", id)));
+    }
+
+    let acked = store.ack(EVENT_STREAM, &group, &ids).await?;
+
+    Ok(HttpResponse::Ok().json(acked))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_ids() {
+        for id in ["1", "42", "1700000000000-0"].iter() {
+            assert!(is_entry_id(id), "{}", id);
+        }
+        for id in ["", "-", "1-", "-1", "1-2-3", "x", "1.5", "+1", " 1"].iter() {
+            assert!(!is_entry_id(id), "{}", id);
+        }
+    }
+}
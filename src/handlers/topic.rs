@@ -1,275 +1,1122 @@
 use crate::{
-    model::{PartialTopic, Settable, Topic, RawPlan, Plan},
-    redis_helper::{redis_add, redis_delete, redis_get_slice},
+    auth::Auth,
+    error::ApiError,
+    handlers::{stream::emit, webhook},
+    live::Live,
+    model::{
+        verify_audit, Action, AuditEntry, EventData, EventKind, FieldError, HistoryEntry, Method, Normalization, PartialTopic,
+        Plan, RawPlan, Role, Schedule, Settable, Status, Topic, User, Vote, VoteError,
+    },
+    store::{self, Store},
+    notify,
+    results::{self, ResultCache},
 };
-use actix::prelude::*;
-use actix_redis::{Command, RedisActor};
-use actix_web::{web, Error as AWError, HttpResponse};
-use futures::future::join;
-use redis_async::{resp::RespValue, resp_array};
+use actix_web::{web, HttpResponse};
+use futures::{future::join, StreamExt};
+use liq::{PollResult, Setting};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::collections::BTreeMap;
+use std::convert::TryInto;
 
-pub async fn get(
-    redis: web::Data<Addr<RedisActor>>,
-    topic_id: web::Path<String>,
-) -> Result<HttpResponse, AWError> {
-    let topic_id = topic_id.into_inner();
+// topics without an owner predate ownership or were made by an admin,
+// only admins can change those
+pub(crate) fn require_role(topic: &Topic, auth: &Auth, role: Role) -> Result<(), ApiError> {
+    let allowed = match auth {
+        Auth::Admin => true,
+        Auth::User(user_id) => topic.role_of(user_id) >= Some(role),
+    };
 
-    let slice = redis_get_slice(&topic_id, "topic", &redis).await;
+    match allowed {
+        true => Ok(()),
+        false => Err(ApiError::Forbidden(format!(
+            "only a topic {} or above can do this",
+            role.as_str()
+        ))),
+    }
+}
+
+/// moderators may only hand out and take away the roles below them,
+/// the owner and admins anything
+fn can_assign(topic: &Topic, auth: &Auth, target: &str, role: Role) -> Result<(), ApiError> {
+    let actor = match auth {
+        Auth::Admin => return Ok(()),
+        Auth::User(user_id) => topic.role_of(user_id),
+    };
 
-    match slice {
-        Some(x) => {
-            let topic: Topic =
-                serde_json::from_slice(&x).expect("this slice should be able to Deserilaze");
-            Ok(HttpResponse::Ok().json(&topic))
+    match actor {
+        Some(Role::Owner) => Ok(()),
+        Some(Role::Moderator)
+            if role < Role::Moderator && topic.role_of(target) < Some(Role::Moderator) =>
+        {
+            Ok(())
         }
-        None => Ok(HttpResponse::NoContent().finish()),
+        _ => Err(ApiError::Forbidden(format!(
+            "cannot change the role of {} in this topic",
+            target
+        ))),
     }
 }
 
-pub async fn delete(
-    redis: web::Data<Addr<RedisActor>>,
+// the owner stays, anyone else can be revoked by whoever could assign
+// their role
+fn can_revoke(topic: &Topic, auth: &Auth, target: &str) -> Result<(), ApiError> {
+    match topic.role_of(target) {
+        Some(Role::Owner) => Err(ApiError::InvalidRequest(
+            "the owner can't be revoked, grant the topic to someone else".into(),
+        )),
+        Some(role) => can_assign(topic, auth, target, role),
+        None => Err(ApiError::NotFound(format!("{} has no role in this topic", target))),
+    }
+}
+
+// checked before a change and again on the topic the change was made
+// to, it may have been closed in between
+fn require_changes(topic: &Topic) -> Result<(), ApiError> {
+    match topic.accepts_changes() {
+        true => Ok(()),
+        false => Err(ApiError::Conflict(format!(
+            "topic is {}, plans and voters can't change",
+            topic.status.as_str()
+        ))),
+    }
+}
+
+fn require_votes(topic: &Topic) -> Result<(), ApiError> {
+    match topic.accepts_votes() {
+        true => Ok(()),
+        false => Err(ApiError::Conflict(format!(
+            "topic is {}, votes are not taken",
+            topic.status.as_str()
+        ))),
+    }
+}
+
+fn check_schedule(schedule: &Schedule) -> Result<(), ApiError> {
+    match schedule.is_valid() {
+        true => Ok(()),
+        false => Err(ApiError::InvalidRequest("closes_at has to be after opens_at".into())),
+    }
+}
+
+/// stores what a committed change produced and tells whoever follows the
+/// topic. settings are keyed by their own hash and audit entries by their
+/// place in the log, so neither is ever overwritten with something else
+/// and neither needs guarding
+async fn record(
+    topic: &Topic,
+    entry: &AuditEntry,
+    store: &web::Data<dyn Store>,
+    live: &Live,
+) -> Result<(), ApiError> {
+    let setting_domain = format!("setting:{}", topic.setting_hash);
+    let setting = topic.setting_json();
+    let entry_domain = entry.domain();
+    let entry_json = entry.json();
+
+    // the log started with this change
+    if let Some(genesis) = topic.genesis() {
+        store.set(&genesis.domain(), genesis.json().as_bytes()).await?;
+    }
+    for step in topic.new_history() {
+        store.set(&step.domain(), step.json().as_bytes()).await?;
+    }
+
+    let (set_setting, set_entry) = join(
+        store.set(&setting_domain, &setting),
+        store.set(&entry_domain, entry_json.as_bytes()),
+    )
+    .await;
+    set_setting.and(set_entry)?;
+
+    // commit calculated it, the next worker that needs it shouldn't have to.
+    // the change is in already, a worker that has to calculate it again
+    // is no reason to fail it
+    if let Some(result) = topic.result() {
+        if let Err(e) = results::keep(&topic.setting_hash, result, store).await {
+            log::error!("could not keep the result of setting:{}: {}", topic.setting_hash, e);
+        }
+    }
+
+    live.publish(topic);
+    notify::fire_committed(store, topic, entry);
+    if let Some(data) = EventData::committed(entry) {
+        emit(store, entry.by.as_deref(), data).await;
+    }
+
+    Ok(())
+}
+
+/// what follows a topic moving on from `from`, by hand or on schedule.
+/// every step goes to the event stream, the scheduler may take two at once
+pub(crate) async fn status_moved(
+    topic: &Topic,
+    from: Status,
+    by: Option<&str>,
+    store: &web::Data<dyn Store>,
+    live: &Live,
+) {
+    let mut status = from;
+
+    while status != topic.status {
+        status = match status.next() {
+            Some(x) => x,
+            None => break,
+        };
+
+        let changed = EventData::StatusChanged { topic_id: topic.id(), status };
+        emit(store, by, changed).await;
+
+        match status {
+            Status::Open => notify::fire_opened(store, topic),
+            Status::Closed => notify::fire_closed(store, topic),
+            _ => {}
+        }
+    }
+
+    live.publish_status(topic);
+}
+
+/// fetches a topic the caller holds at least `role` in
+pub(crate) async fn topic_as(
+    topic_id: &str,
+    store: &web::Data<dyn Store>,
+    auth: &Auth,
+    role: Role,
+) -> Result<Topic, ApiError> {
+    let topic: Topic = store::get(topic_id, store)
+        .await?
+        .ok_or_else(|| ApiError::not_found("topic", topic_id))?;
+
+    require_role(&topic, auth, role)?;
+
+    Ok(topic)
+}
+
+pub async fn get(
+    store: web::Data<dyn Store>,
     topic_id: web::Path<String>,
-) -> Result<HttpResponse, AWError> {
+) -> Result<HttpResponse, ApiError> {
     let topic_id = topic_id.into_inner();
 
-    let data = redis_get_slice(&topic_id, "topic", &redis).await;
+    let topic: Topic = store::get(&topic_id, &store)
+        .await?
+        .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
 
-    let topic: Topic = match data {
-        Some(x) => serde_json::from_slice(&x).unwrap(),
-        None => return Ok(HttpResponse::NoContent().finish()),
-    };
+    Ok(HttpResponse::Ok().json(&topic))
+}
 
-    let did_delete = redis_delete(topic, &redis).await;
+/// server-sent events with the result of the topic, one right away and
+/// one for every change after that
+pub async fn events(
+    store: web::Data<dyn Store>,
+    live: web::Data<Live>,
+    topic_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let topic_id = topic_id.into_inner();
 
-    match did_delete {
-        true => Ok(HttpResponse::Ok().body("deleted topic")),
-        false => Ok(HttpResponse::InternalServerError().finish()),
-    }
+    let topic: Topic = store::get(&topic_id, &store)
+        .await?
+        .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
+
+    let events = live.subscribe(&topic).map(Ok::<_, actix_web::Error>);
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .streaming(events))
 }
 
-pub async fn list(redis: web::Data<Addr<RedisActor>>) -> Result<HttpResponse, AWError> {
-    let res = redis
-        .send(Command(resp_array!["SMEMBERS", "topics",]))
-        .await?; // get all
+/// the audit log stays behind, and with it the id: `put` won't create a
+/// topic in its place
+pub async fn delete(
+    store: web::Data<dyn Store>,
+    topic_id: web::Path<String>,
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
+    let topic_id = topic_id.into_inner();
 
-    if let Ok(RespValue::Array(ids)) = res {
-        let mut list: Vec<Vec<String>> = Vec::new();
-        for id in ids {
-            if let RespValue::BulkString(v) = id {
-                let (topic_id, topic_title) = serde_json::from_slice(&v).unwrap();
-                list.push(vec![topic_id, topic_title]);
-            }
+    let topic = topic_as(&topic_id, &store, &auth, Role::Owner).await?;
+
+    webhook::delete_for_topic(&topic_id, &store).await?;
+
+    match store::delete(topic, &store).await? {
+        true => {
+            emit(&store, auth.user_id(), EventData::TopicDeleted { topic_id }).await;
+            Ok(HttpResponse::Ok().body("deleted topic"))
         }
-        Ok(HttpResponse::Ok().json(list))
-    } else {
-        Ok(HttpResponse::InternalServerError().finish())
+        // someone else deleted it in the meantime
+        false => Err(ApiError::not_found("topic", &topic_id)),
     }
 }
 
+pub async fn list(store: web::Data<dyn Store>) -> Result<HttpResponse, ApiError> {
+    let items = store::get_list("topic", &store).await?; // get all
+
+    let list: Vec<Vec<String>> = items
+        .into_iter()
+        .map(|(topic_id, topic_title)| vec![topic_id, topic_title])
+        .collect();
+
+    Ok(HttpResponse::Ok().json(list))
+}
+
 pub async fn put(
-    redis: web::Data<Addr<RedisActor>>,
+    store: web::Data<dyn Store>,
     topic: web::Json<PartialTopic>,
-) -> Result<HttpResponse, AWError> {
-    let topic: Topic = topic.into_inner().into();
-    let id: String = topic.id().to_string();
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
+    let topic = topic.into_inner();
+    check_schedule(&topic.schedule())?;
+
+    let mut topic: Topic = topic.into();
+    topic.owner = auth.user_id().map(str::to_string);
+    let id: String = topic.id();
 
-    match redis_add(topic, &redis).await {
-        true => Ok(HttpResponse::Ok().json(id)),
-        false => Ok(HttpResponse::InternalServerError().body("could not put topic")),
+    // the id comes from the title and description. posting the same text
+    // again would start the topic, and its audit log, over
+    if store::get::<Topic>(&id, &store).await?.is_some() {
+        return Err(ApiError::Conflict(format!("topic {} exists already", id)));
     }
+    if store.get(&format!("audit:{}:1", id)).await?.is_some() {
+        return Err(ApiError::Conflict(format!(
+            "topic {} was deleted, its audit log is kept. change the title or description",
+            id
+        )));
+    }
+
+    store::add(topic, &store).await?;
+    notify::fire(&store, &id, EventKind::TopicCreated, json!({ "topic_id": id }));
+    emit(&store, auth.user_id(), EventData::TopicCreated { topic_id: id.to_owned() }).await;
+
+    Ok(HttpResponse::Ok().json(id))
 }
 
 // this adds the plan to the db and appends to the 
 // votes list.
 pub async fn add_plan(
-    redis: web::Data<Addr<RedisActor>>,
+    store: web::Data<dyn Store>,
+    live: web::Data<Live>,
+    results: web::Data<ResultCache>,
     topic_id: web::Path<String>,
-    raw_plan: web::Json<RawPlan>
-    ) -> Result<HttpResponse, AWError>{
+    raw_plan: web::Json<RawPlan>,
+    auth: Auth,
+    ) -> Result<HttpResponse, ApiError>{
 
     let topic_id = topic_id.into_inner();
 
-    let topic_slice = redis_get_slice(&topic_id, "topic", &redis).await;
+    let plan: Plan = raw_plan.into_inner().try_into()?;
+    let plan_id = plan.id();
 
-    dbg!(&topic_id);
+    let topic = topic_as(&topic_id, &store, &auth, Role::Moderator).await?;
+    require_changes(&topic)?;
 
-    let mut topic: Topic = match topic_slice {
-        Some(x) => serde_json::from_slice(&x).expect("slice should be Deserilazable"),
-        None => return Ok(HttpResponse::NoContent().body("could not retrieve topic")),
-    };
+    // the plan has to exist before the topic points to it
+    store::add(plan, &store).await?;
 
-    let plan: Plan = raw_plan.into_inner().into();
-    let plan_id = plan.id();
+    let mut entry = None;
 
-    topic.add_plan_id(&plan_id);
+    let topic: Topic = store::modify(&topic_id, &store, |topic: &mut Topic| {
+        entry = None;
+        if topic.accepts_changes() {
+            topic.add_plan_id(&plan_id);
+            entry = topic.commit(auth.user_id(), Action::AddPlan { plan_id: plan_id.to_owned() }, &results);
+        }
+    })
+    .await?
+    .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
 
-    let add  = join(
-        redis_add(topic, &redis), 
-        redis_add(plan, &redis)
-        ).await;
+    require_changes(&topic)?;
 
-    match add {
-        (true, true) => Ok(HttpResponse::Ok().json((topic_id, plan_id))),
-        _ => Ok(HttpResponse::InternalServerError().body("could not add new topic and plan to the db"))
+    if let Some(entry) = &entry {
+        record(&topic, entry, &store, &live).await?;
     }
+
+    Ok(HttpResponse::Ok().json((topic_id, plan_id)))
 }
 
 
 pub async fn add_plan_id(
-    redis: web::Data<Addr<RedisActor>>,
+    store: web::Data<dyn Store>,
+    live: web::Data<Live>,
+    results: web::Data<ResultCache>,
     path: web::Path<(String, String)>,
-) -> Result<HttpResponse, AWError> {
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
     let (topic_id, plan_id) = path.into_inner();
 
-    let data = redis_get_slice(&topic_id, "topic", &redis).await;
+    let topic = topic_as(&topic_id, &store, &auth, Role::Moderator).await?;
+    require_changes(&topic)?;
 
-    let mut topic: Topic = match data {
-        Some(x) => serde_json::from_slice(&x).expect("slice should be Deserilazable"),
-        None => return Ok(HttpResponse::NoContent().body("could not retrieve topic")),
-    };
+    // add the plan_id and save the new topic data
+    let mut entry = None;
 
-    // add the plan_id
-    topic.add_plan_id(&plan_id);
+    let topic: Topic = store::modify(&topic_id, &store, |topic: &mut Topic| {
+        entry = None;
+        if topic.accepts_changes() {
+            topic.add_plan_id(&plan_id);
+            entry = topic.commit(auth.user_id(), Action::AddPlan { plan_id: plan_id.to_owned() }, &results);
+        }
+    })
+    .await?
+    .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
 
-    // save the new topic data
-    let res = redis
-        .send(Command(resp_array!["SET", &topic.domain(), &topic.json()]))
-        .await?;
+    require_changes(&topic)?;
 
-    // respond
-    match res {
-        Ok(RespValue::SimpleString(x)) if x == "OK" => Ok(HttpResponse::Ok().json(topic)),
-        _ => Ok(HttpResponse::InternalServerError().finish()),
+    if let Some(entry) = &entry {
+        record(&topic, entry, &store, &live).await?;
     }
-}
 
-type Vote = BTreeMap<String, f64>;
+    Ok(HttpResponse::Ok().json(topic))
+}
 
 pub async fn update_vote_and_calculate(
-    redis: web::Data<Addr<RedisActor>>,
+    store: web::Data<dyn Store>,
+    live: web::Data<Live>,
+    results: web::Data<ResultCache>,
     path: web::Path<(String, String)>,
     vote: web::Json<Vote>,
-) -> Result<HttpResponse, AWError> {
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
     let (topic_id, user_id) = path.into_inner();
 
-    let data = redis_get_slice(&topic_id, "topic", &redis).await;
+    match cast_vote(&store, &live, &results, &topic_id, &user_id, vote.into_inner(), &auth).await? {
+        Some(topic) => Ok(HttpResponse::Ok().json(topic)),
+        // no change
+        None => Ok(HttpResponse::Ok().json("no change")),
+    }
+}
+
+// observers and anyone else who was never added can't vote
+fn check_ballot(
+    topic: &Topic,
+    user_id: &str,
+    vote: Vote,
+    normalization: Normalization,
+) -> Result<Vote, ApiError> {
+    if !topic.is_voter(user_id) {
+        return Err(ApiError::Forbidden(format!("{} is not a voter of this topic", user_id)));
+    }
+
+    Ok(topic.check_vote(user_id, vote, normalization)?)
+}
+
+/// puts the vote of `user_id` on the topic and recalculates, `None` if the
+/// vote was the same as before. shared with the websocket sessions
+pub async fn cast_vote(
+    store: &web::Data<dyn Store>,
+    live: &Live,
+    results: &ResultCache,
+    topic_id: &str,
+    user_id: &str,
+    vote: Vote,
+    auth: &Auth,
+) -> Result<Option<Topic>, ApiError> {
+    // nobody votes in someone else's name
+    if !auth.is(user_id) {
+        return Err(ApiError::Forbidden(format!("cannot vote as {}", user_id)));
+    }
+
+    let topic: Topic = store::get(topic_id, store)
+        .await?
+        .ok_or_else(|| ApiError::not_found("topic", topic_id))?;
+
+    let normalization = Normalization::from_env();
+    check_ballot(&topic, user_id, vote.clone(), normalization)?;
+    require_votes(&topic)?;
+
+    let mut entry = None;
+    let mut rejected = None;
 
-    let mut topic: Topic = match data {
-        Some(x) => serde_json::from_slice(&x).expect("slice should be Deserilazable"),
-        _ => {
-            return Ok(HttpResponse::InternalServerError().finish());
+    // plans and voters may have gone since the checks above, so they run
+    // again on the topic that is written
+    let topic: Topic = store::modify(topic_id, store, |topic: &mut Topic| {
+        entry = None;
+        rejected = None;
+        if !topic.accepts_votes() {
+            return;
         }
-    };
 
-    let vote = vote.into_inner();
+        let vote = match check_ballot(topic, user_id, vote.clone(), normalization) {
+            Ok(x) => x,
+            Err(e) => {
+                rejected = Some(e);
+                return;
+            }
+        };
 
-    let new_hash = topic.insert_vote(&user_id, vote);
-    // let's just compute it each time for now.
+        topic.insert_vote(user_id, vote.clone());
+        entry = topic.commit(auth.user_id(), Action::Vote {
+            voter: user_id.to_owned(),
+            vote,
+        }, results);
+    })
+    .await?
+    .ok_or_else(|| ApiError::not_found("topic", topic_id))?;
 
-    if new_hash != topic.setting_hash {
-        topic.update_setting_hash(&new_hash);
-        topic.calculate();
-        let set_topic = redis.send(Command(resp_array!["SET", &topic.domain(), &topic.json()]));
+    if let Some(e) = rejected {
+        return Err(e);
+    }
+    require_votes(&topic)?;
 
-        let setting_domain = format!("setting:{}", topic.setting_hash);
-        let set_setting = redis.send(Command(resp_array![
-            "SET",
-            setting_domain,
-            topic.setting_json()
-        ]));
+    match &entry {
+        Some(entry) => {
+            record(&topic, entry, store, live).await?;
+            Ok(Some(topic))
+        }
+        None => Ok(None),
+    }
+}
+
+/// what the result would be with these ballots, keyed by voter, in place
+/// of the stored ones. the ballots are checked like real votes but
+/// nothing is saved.
+pub async fn simulate(
+    store: web::Data<dyn Store>,
+    results: web::Data<ResultCache>,
+    topic_id: web::Path<String>,
+    votes: web::Json<BTreeMap<String, Vote>>,
+) -> Result<HttpResponse, ApiError> {
+    let topic_id = topic_id.into_inner();
 
-        let (res, _) = join(set_topic, set_setting).await;
+    let topic: Topic = store::get(&topic_id, &store)
+        .await?
+        .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
 
-        match res? {
-            Ok(RespValue::SimpleString(x)) if x == "OK" => Ok(HttpResponse::Ok().json(topic)),
-            _ => Ok(HttpResponse::InternalServerError().body("cannot save new topic")),
+    let normalization = Normalization::from_env();
+    let mut checked = BTreeMap::new();
+    let mut errors = Vec::new();
+
+    for (voter, vote) in votes.into_inner() {
+        if !topic.is_voter(&voter) {
+            errors.push(FieldError {
+                field: voter.to_owned(),
+                message: "is not a voter of this topic".to_string(),
+            });
+            continue;
         }
-    } else {
-        // no change
-        Ok(HttpResponse::Ok().json("no change"))
+
+        match topic.check_vote(&voter, vote, normalization) {
+            Ok(vote) => {
+                checked.insert(voter, vote);
+            }
+            // the fields are per ballot here, say whose
+            Err(VoteError(e)) => errors.extend(e.into_iter().map(|x| FieldError {
+                field: format!("{}.{}", voter, x.field),
+                ..x
+            })),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(VoteError(errors).into());
     }
+
+    // asking again with the same ballots is common, storing them is not worth it
+    let result = results.calculate(&topic.with_votes(checked));
+
+    Ok(HttpResponse::Ok().json(result))
 }
 
 pub async fn remove_plan_id(
-    redis: web::Data<Addr<RedisActor>>,
+    store: web::Data<dyn Store>,
+    live: web::Data<Live>,
+    results: web::Data<ResultCache>,
     path: web::Path<(String, String)>,
-) -> Result<HttpResponse, AWError> {
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
     let (topic_id, text) = path.into_inner();
 
-    // get the topic data
-    let data = redis_get_slice(&topic_id, "topic", &redis).await;
+    let topic = topic_as(&topic_id, &store, &auth, Role::Moderator).await?;
+    require_changes(&topic)?;
 
-    let mut topic: Topic = match data {
-        Some(x) => serde_json::from_slice(&x).expect("sould Deserilazable"),
-        None => return Ok(HttpResponse::NoContent().finish()),
-    };
+    let mut entry = None;
 
-    topic.remove_plan_id(&text);
+    let topic: Topic = store::modify(&topic_id, &store, |topic: &mut Topic| {
+        entry = None;
+        if topic.accepts_changes() {
+            topic.remove_plan_id(&text);
+            entry = topic.commit(auth.user_id(), Action::RemovePlan { plan_id: text.to_owned() }, &results);
+        }
+    })
+    .await?
+    .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
 
-    // save the new topic data
-    let res = redis
-        .send(Command(resp_array!["SET", &topic.domain(), &topic.json()]))
-        .await?;
+    require_changes(&topic)?;
 
-    // respond
-    match res {
-        Ok(RespValue::SimpleString(x)) if x == "OK" => Ok(HttpResponse::Ok().json(topic)),
-        _ => Ok(HttpResponse::InternalServerError().finish()),
+    if let Some(entry) = &entry {
+        record(&topic, entry, &store, &live).await?;
     }
+
+    Ok(HttpResponse::Ok().json(topic))
 }
 
 pub async fn add_user(
-    redis: web::Data<Addr<RedisActor>>,
+    store: web::Data<dyn Store>,
+    live: web::Data<Live>,
+    results: web::Data<ResultCache>,
     path: web::Path<(String, String)>,
-) -> Result<HttpResponse, AWError> {
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
     let (topic_id, user_id) = path.into_inner();
 
-    let data = redis_get_slice(&topic_id, "topic", &redis).await;
+    let topic = topic_as(&topic_id, &store, &auth, Role::Moderator).await?;
+    require_changes(&topic)?;
 
-    let mut topic: Topic = match data {
-        Some(x) => serde_json::from_slice(&x).unwrap(),
-        None => return Ok(HttpResponse::InternalServerError().finish()),
-    };
+    if store::get::<User>(&user_id, &store).await?.is_none() {
+        return Err(ApiError::not_found("user", &user_id));
+    }
+
+    let mut entry = None;
 
-    // add the plan
-    topic.add_user(user_id);
+    let topic: Topic = store::modify(&topic_id, &store, |topic: &mut Topic| {
+        entry = None;
+        if topic.accepts_changes() {
+            topic.add_user(user_id.to_owned());
+            entry = topic.commit(auth.user_id(), Action::AddVoter { voter: user_id.to_owned() }, &results);
+        }
+    })
+    .await?
+    .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
 
-    // save the new topic data
-    let res = redis
-        .send(Command(resp_array!["SET", &topic.domain(), &topic.json()]))
-        .await?;
+    require_changes(&topic)?;
 
-    // respond
-    match res {
-        Ok(RespValue::SimpleString(x)) if x == "OK" => Ok(HttpResponse::Ok().json(topic)),
-        _ => Ok(HttpResponse::InternalServerError().finish()),
+    if let Some(entry) = &entry {
+        record(&topic, entry, &store, &live).await?;
     }
+
+    Ok(HttpResponse::Ok().json(topic))
 }
 
 pub async fn remove_user(
-    redis: web::Data<Addr<RedisActor>>,
+    store: web::Data<dyn Store>,
+    live: web::Data<Live>,
+    results: web::Data<ResultCache>,
     path: web::Path<(String, String)>,
-) -> Result<HttpResponse, AWError> {
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
     let (topic_id, user_id) = path.into_inner();
 
-    let data = redis_get_slice(&topic_id, "topic", &redis).await;
+    let topic = topic_as(&topic_id, &store, &auth, Role::Moderator).await?;
+    require_changes(&topic)?;
+
+    let mut entry = None;
+
+    let topic: Topic = store::modify(&topic_id, &store, |topic: &mut Topic| {
+        entry = None;
+        if topic.accepts_changes() {
+            topic.remove_user(user_id.to_owned());
+            entry = topic.commit(auth.user_id(), Action::RemoveVoter { voter: user_id.to_owned() }, &results);
+        }
+    })
+    .await?
+    .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
+
+    require_changes(&topic)?;
+
+    if let Some(entry) = &entry {
+        record(&topic, entry, &store, &live).await?;
+    }
+
+    Ok(HttpResponse::Ok().json(topic))
+}
+
+pub async fn set_status(
+    store: web::Data<dyn Store>,
+    live: web::Data<Live>,
+    topic_id: web::Path<String>,
+    status: web::Json<Status>,
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
+    let topic_id = topic_id.into_inner();
+    let status = status.into_inner();
+
+    topic_as(&topic_id, &store, &auth, Role::Moderator).await?;
+
+    let mut moved = false;
+    let mut from = status;
+
+    let topic: Topic = store::modify(&topic_id, &store, |topic: &mut Topic| {
+        from = topic.status;
+        moved = topic.set_status(status);
+    })
+    .await?
+    .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
+
+    if moved {
+        status_moved(&topic, from, auth.user_id(), &store, &live).await;
+    }
+
+    match moved {
+        true => Ok(HttpResponse::Ok().json(topic)),
+        false => Err(ApiError::Conflict(format!(
+            "topic is {}, it can't become {}",
+            topic.status.as_str(),
+            status.as_str()
+        ))),
+    }
+}
+
+/// replaces both times, leave one out to unset it
+pub async fn set_schedule(
+    store: web::Data<dyn Store>,
+    topic_id: web::Path<String>,
+    schedule: web::Json<Schedule>,
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
+    let topic_id = topic_id.into_inner();
+    let schedule = schedule.into_inner();
+
+    check_schedule(&schedule)?;
+
+    let topic = topic_as(&topic_id, &store, &auth, Role::Moderator).await?;
+    require_changes(&topic)?;
+
+    let topic: Topic = store::modify(&topic_id, &store, |topic: &mut Topic| {
+        if topic.accepts_changes() {
+            topic.set_schedule(schedule);
+        }
+    })
+    .await?
+    .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
 
-    let mut topic: Topic = match data {
-        Some(x) => serde_json::from_slice(&x).unwrap(),
-        _ => return Ok(HttpResponse::InternalServerError().finish()),
+    require_changes(&topic)?;
+
+    let changed = EventData::ScheduleChanged {
+        topic_id,
+        opens_at: schedule.opens_at,
+        closes_at: schedule.closes_at,
+    };
+    emit(&store, auth.user_id(), changed).await;
+
+    Ok(HttpResponse::Ok().json(topic))
+}
+
+/// how the topic is counted, only while plans and voters may still change
+pub async fn set_method(
+    store: web::Data<dyn Store>,
+    topic_id: web::Path<String>,
+    method: web::Json<Method>,
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
+    let topic_id = topic_id.into_inner();
+    let method = method.into_inner();
+
+    let topic = topic_as(&topic_id, &store, &auth, Role::Moderator).await?;
+    require_changes(&topic)?;
+
+    let topic: Topic = store::modify(&topic_id, &store, |topic: &mut Topic| {
+        if topic.accepts_changes() {
+            topic.method = method;
+        }
+    })
+    .await?
+    .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
+
+    require_changes(&topic)?;
+
+    emit(&store, auth.user_id(), EventData::MethodChanged { topic_id, method }).await;
+
+    Ok(HttpResponse::Ok().json(topic))
+}
+
+#[derive(Deserialize)]
+pub struct TallyQuery {
+    method: Option<String>,
+}
+
+/// the ballots counted with the topic's method, `?method=borda` for
+/// another one or `?method=all` to compare them all
+pub async fn tally(
+    store: web::Data<dyn Store>,
+    topic_id: web::Path<String>,
+    query: web::Query<TallyQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let topic_id = topic_id.into_inner();
+
+    let topic: Topic = store::get(&topic_id, &store)
+        .await?
+        .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
+
+    let method = match query.method.as_deref() {
+        None => topic.method,
+        Some("all") => {
+            let all: Vec<_> = Method::ALL.iter().map(|x| topic.tally(*x)).collect();
+            return Ok(HttpResponse::Ok().json(all));
+        }
+        Some(x) => serde_json::from_value(serde_json::Value::String(x.to_string()))
+            .map_err(|_| ApiError::InvalidRequest(format!("unknown tally method '{}'", x)))?,
+    };
+
+    Ok(HttpResponse::Ok().json(topic.tally(method)))
+}
+
+pub async fn list_roles(
+    store: web::Data<dyn Store>,
+    topic_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let topic_id = topic_id.into_inner();
+
+    let topic: Topic = store::get(&topic_id, &store)
+        .await?
+        .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
+
+    Ok(HttpResponse::Ok().json(topic.roles()))
+}
+
+pub async fn grant_role(
+    store: web::Data<dyn Store>,
+    live: web::Data<Live>,
+    results: web::Data<ResultCache>,
+    path: web::Path<(String, String)>,
+    role: web::Json<Role>,
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
+    let (topic_id, user_id) = path.into_inner();
+    let role = role.into_inner();
+
+    let topic = topic_as(&topic_id, &store, &auth, Role::Moderator).await?;
+    can_assign(&topic, &auth, &user_id, role)?;
+
+    // these change who is in the setting
+    let changes_setting = matches!(role, Role::Voter | Role::Observer);
+    if changes_setting {
+        require_changes(&topic)?;
+    }
+
+    if store::get::<User>(&user_id, &store).await?.is_none() {
+        return Err(ApiError::not_found("user", &user_id));
+    }
+
+    let action = match role {
+        Role::Voter => Some(Action::AddVoter { voter: user_id.to_owned() }),
+        Role::Observer => Some(Action::RemoveVoter { voter: user_id.to_owned() }),
+        _ => None,
     };
+    let mut entry = None;
+    let mut rejected = None;
+
+    // the actor's own role may have changed since the check above
+    let topic: Topic = store::modify(&topic_id, &store, |topic: &mut Topic| {
+        entry = None;
+        rejected = None;
+        if let Err(e) = can_assign(topic, &auth, &user_id, role) {
+            rejected = Some(e);
+            return;
+        }
+        if changes_setting && !topic.accepts_changes() {
+            return;
+        }
+
+        topic.grant(&user_id, role);
+        entry = match &action {
+            Some(action) => topic.commit(auth.user_id(), action.to_owned(), &results),
+            None => None,
+        };
+    })
+    .await?
+    .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
+
+    if let Some(e) = rejected {
+        return Err(e);
+    }
+    if changes_setting {
+        require_changes(&topic)?;
+    }
+
+    if let Some(entry) = &entry {
+        record(&topic, entry, &store, &live).await?;
+    }
+
+    emit(&store, auth.user_id(), EventData::RoleGranted { topic_id, user_id, role }).await;
+
+    Ok(HttpResponse::Ok().json(topic.roles()))
+}
+
+pub async fn revoke_role(
+    store: web::Data<dyn Store>,
+    live: web::Data<Live>,
+    results: web::Data<ResultCache>,
+    path: web::Path<(String, String)>,
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
+    let (topic_id, user_id) = path.into_inner();
+
+    let topic = topic_as(&topic_id, &store, &auth, Role::Moderator).await?;
+    can_revoke(&topic, &auth, &user_id)?;
+
+    // revoking also takes a voter out of the setting, moderators may be voters too
+    if topic.is_voter(&user_id) {
+        require_changes(&topic)?;
+    }
+
+    let mut entry = None;
+    let mut rejected = None;
+    let mut was_voter = false;
+
+    // either role may have changed since the check above
+    let topic: Topic = store::modify(&topic_id, &store, |topic: &mut Topic| {
+        entry = None;
+        rejected = None;
+        was_voter = false;
+        if let Err(e) = can_revoke(topic, &auth, &user_id) {
+            rejected = Some(e);
+            return;
+        }
+
+        was_voter = topic.is_voter(&user_id);
+        if was_voter && !topic.accepts_changes() {
+            return;
+        }
+
+        topic.revoke(&user_id);
+        if was_voter {
+            entry = topic.commit(auth.user_id(), Action::RemoveVoter { voter: user_id.to_owned() }, &results);
+        }
+    })
+    .await?
+    .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
+
+    if let Some(e) = rejected {
+        return Err(e);
+    }
+    if was_voter {
+        require_changes(&topic)?;
+    }
+
+    if let Some(entry) = &entry {
+        record(&topic, entry, &store, &live).await?;
+    }
+
+    emit(&store, auth.user_id(), EventData::RoleRevoked { topic_id, user_id }).await;
+
+    Ok(HttpResponse::Ok().json(topic.roles()))
+}
+
+pub async fn history(
+    store: web::Data<dyn Store>,
+    topic_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let topic_id = topic_id.into_inner();
+
+    let topic: Topic = store::get(&topic_id, &store)
+        .await?
+        .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
+
+    Ok(HttpResponse::Ok().json(history_log(&topic, &store).await?))
+}
+
+// stored entries first, an older topic's own after them until its next change
+async fn history_log(
+    topic: &Topic,
+    store: &web::Data<dyn Store>,
+) -> Result<Vec<HistoryEntry>, ApiError> {
+    let ids: Vec<String> = (1..=topic.history_seq())
+        .map(|seq| format!("{}:{}", topic.id(), seq))
+        .collect();
+
+    let entries = store::get_all::<HistoryEntry>(&ids, "history", store).await?;
+
+    Ok(entries
+        .into_iter()
+        .map(|(_, entry)| entry)
+        .chain(topic.inline_history().iter().cloned())
+        .collect())
+}
+
+#[derive(Serialize)]
+struct AtSetting<'a> {
+    entry: HistoryEntry,
+    setting: &'a Setting,
+    result: PollResult,
+}
+
+/// the setting and result of a topic as they were at `setting_hash`
+pub async fn at_setting(
+    store: web::Data<dyn Store>,
+    results: web::Data<ResultCache>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (topic_id, setting_hash) = path.into_inner();
+
+    let topic: Topic = store::get(&topic_id, &store)
+        .await?
+        .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
+
+    // only hashes this topic went through, not any stored setting
+    let entry = history_log(&topic, &store)
+        .await?
+        .into_iter()
+        .find(|x| x.hash == setting_hash)
+        .ok_or_else(|| {
+            ApiError::NotFound(format!("topic:{} never had setting {}", topic_id, setting_hash))
+        })?;
+
+    let setting: Setting = store::get(&setting_hash, &store)
+        .await?
+        .ok_or_else(|| ApiError::not_found("setting", &setting_hash))?;
+
+    let result = results.calculate_stored(&setting, &store).await?;
+
+    Ok(HttpResponse::Ok().json(AtSetting {
+        entry,
+        setting: &setting,
+        result,
+    }))
+}
+
+// entries that went missing are left out, `verify_audit` points them out
+async fn audit_log(
+    topic: &Topic,
+    store: &web::Data<dyn Store>,
+) -> Result<Vec<AuditEntry>, ApiError> {
+    let ids: Vec<String> = (1..=topic.audit_head().seq)
+        .map(|seq| format!("{}:{}", topic.id(), seq))
+        .collect();
+
+    let entries = store::get_all::<AuditEntry>(&ids, "audit", store).await?;
+
+    Ok(entries.into_iter().map(|(_, entry)| entry).collect())
+}
+
+pub async fn audit(
+    store: web::Data<dyn Store>,
+    topic_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let topic_id = topic_id.into_inner();
+
+    let topic: Topic = store::get(&topic_id, &store)
+        .await?
+        .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
+
+    Ok(HttpResponse::Ok().json(audit_log(&topic, &store).await?))
+}
+
+/// replays the audit log of a topic and checks it against the topic
+pub async fn verify(
+    store: web::Data<dyn Store>,
+    topic_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let topic_id = topic_id.into_inner();
+
+    let topic: Topic = store::get(&topic_id, &store)
+        .await?
+        .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
+
+    let entries = audit_log(&topic, &store).await?;
+
+    Ok(HttpResponse::Ok().json(verify_audit(&entries, topic.audit_head(), &topic.setting_hash)))
+}
+
+/// proves `user_id`'s ballot is part of the topic's `ballot_root`. the
+/// ballots are public, in the topic, its audit log and the export, so
+/// anyone may check anyone's
+pub async fn ballot_proof(
+    store: web::Data<dyn Store>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (topic_id, user_id) = path.into_inner();
+
+    let topic: Topic = store::get(&topic_id, &store)
+        .await?
+        .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
+
+    let root = match (topic.status, &topic.ballot_root) {
+        (Status::Closed, Some(root)) | (Status::Archived, Some(root)) => root,
+        (Status::Closed, None) | (Status::Archived, None) => {
+            return Err(ApiError::NotFound(format!("topic:{} closed without ballots", topic_id)))
+        }
+        (status, _) => {
+            return Err(ApiError::Conflict(format!(
+                "topic is {}, ballots are committed when it closes",
+                status.as_str()
+            )))
+        }
+    };
+
+    let proof = topic
+        .ballot_tree()
+        .proof(&user_id)
+        .ok_or_else(|| ApiError::NotFound(format!("{} has no ballot in this topic", user_id)))?;
+
+    // the setting is frozen at close, so this only fails if it was tampered with
+    if &proof.root != root {
+        return Err(ApiError::Conflict("the ballots no longer match the published root".into()));
+    }
+
+    Ok(HttpResponse::Ok().json(proof))
+}
+
+#[derive(Deserialize)]
+pub struct GraphQuery {
+    format: Option<String>,
+}
+
+/// the delegation graph of a topic, `?format=dot` for GraphViz,
+/// json nodes and edges otherwise
+pub async fn graph(
+    store: web::Data<dyn Store>,
+    topic_id: web::Path<String>,
+    query: web::Query<GraphQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let topic_id = topic_id.into_inner();
+
+    let topic: Topic = store::get(&topic_id, &store)
+        .await?
+        .ok_or_else(|| ApiError::not_found("topic", &topic_id))?;
+
+    let graph = topic.delegation_graph();
+
+    match query.format.as_deref() {
+        None | Some("json") => Ok(HttpResponse::Ok().json(graph)),
+        Some("dot") => Ok(HttpResponse::Ok()
+            .content_type("text/vnd.graphviz")
+            .body(graph.to_dot())),
+        Some(x) => Err(ApiError::InvalidRequest(format!(
+            "unknown graph format '{}', expected json or dot",
+            x
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::rt::System;
+
+    fn partial() -> web::Json<PartialTopic> {
+        web::Json(serde_json::from_value(json!({"title": "t", "description": "d"})).unwrap())
+    }
+
+    fn alice() -> Auth {
+        Auth::User("alice".to_string())
+    }
+
+    #[test]
+    fn a_topic_is_never_started_over() {
+        System::new("test").block_on(async {
+            let store = store::memory();
+            let results = ResultCache::new(10);
 
-    topic.remove_user(user_id);
+            put(store.clone(), partial(), alice()).await.unwrap();
+            let id = Topic::from(partial().into_inner()).id();
+            assert!(matches!(put(store.clone(), partial(), alice()).await, Err(ApiError::Conflict(_))));
 
-    // save the new topic data
-    let res = redis
-        .send(Command(resp_array!["SET", &topic.domain(), &topic.json()]))
-        .await?;
+            // a log to lose
+            let mut genesis = None;
+            store::modify(&id, &store, |topic: &mut Topic| {
+                topic.add_plan_id("p");
+                topic.commit(Some("alice"), Action::AddPlan { plan_id: "p".into() }, &results);
+                genesis = topic.genesis().cloned();
+            })
+            .await
+            .unwrap();
+            let genesis = genesis.unwrap();
+            store.set(&genesis.domain(), genesis.json().as_bytes()).await.unwrap();
 
-    // respond
-    match res {
-        Ok(RespValue::SimpleString(x)) if x == "OK" => Ok(HttpResponse::Ok().json(topic)),
-        _ => Ok(HttpResponse::InternalServerError().finish()),
+            delete(store.clone(), web::Path::from(id.to_owned()), alice()).await.unwrap();
+            assert!(matches!(put(store.clone(), partial(), alice()).await, Err(ApiError::Conflict(_))));
+            assert!(store.get(&genesis.domain()).await.unwrap().is_some());
+        });
     }
 }
@@ -1,110 +1,101 @@
-use crate::auth::{check_auth, generate_access_token, generate_temp_code};
+use crate::auth::{
+    bearer_token, check_auth, create_session, generate_temp_code, list_sessions,
+    revoke_all_sessions, revoke_session, session_id,
+};
 use crate::send_mail::async_send_mail;
 use crate::{
-    auth::compose_temp_code_mail,
-    model::{PartialUser, Settable, User},
-    redis_helper::{redis_add, redis_get_slice, redis_delete}
+    auth::{compose_temp_code_mail, Auth},
+    error::ApiError,
+    handlers::stream::emit,
+    model::{EventData, PartialUser, Settable, User},
+    store::{self, Store, StoreError},
 };
-use actix::prelude::*;
-use actix_redis::{Command, RedisActor};
-use actix_web::{Error as AWError, HttpRequest, HttpResponse, web};
-use futures::future::{join_all, join};
-use redis_async::{resp::RespValue, resp_array};
+use actix_web::{HttpRequest, HttpResponse, web};
+use futures::future::join;
 
 /// gets a list of users nickname from a list of ids...
 /// it will be [[id, nickname]]
 pub async fn from_ids(
-    redis: web::Data<Addr<RedisActor>>,
+    store: web::Data<dyn Store>,
     user_ids: web::Json<Vec<String>>,
-) -> Result<HttpResponse, AWError> {
+) -> Result<HttpResponse, ApiError> {
     let user_ids = user_ids.into_inner();
 
-    let commands = user_ids.iter().map(|id| {
-        let domain = format!("user:{}", id);
-        redis.send(Command(resp_array!["GET", &domain]))
-    });
+    let users = store::get_all::<User>(&user_ids, "user", &store).await?;
+
+    // get_all skips missing users, so every id has to come back
+    if users.len() != user_ids.len() {
+        let missing: Vec<&str> = user_ids
+            .iter()
+            .filter(|id| !users.iter().any(|(found, _)| found == *id))
+            .map(|id| id.as_str())
+            .collect();
+        return Err(ApiError::NotFound(format!("unknown users: {}", missing.join(", "))));
+    }
 
-    let res: Vec<Result<RespValue, AWError>> = join_all(commands.into_iter())
-        .await
+    let user_nicknames: Vec<(String, String)> = users
         .into_iter()
-        .map(|item| {
-            item.map_err(AWError::from)
-                .and_then(|res| res.map_err(AWError::from))
-        })
+        .map(|(_, user)| (user.id(), user.nickname))
         .collect();
 
-    let mut user_nicknames: Vec<(String, String)> = Vec::new();
-
-    if !res.iter().all(|res| match res {
-        Ok(RespValue::BulkString(x)) => {
-            let user:User = serde_json::from_slice(x).expect("I should be able to deserialize");
-            user_nicknames.push((user.id(), user.nickname));
-            true
-        }
-        _ => false,
-    }) {
-        Ok(HttpResponse::InternalServerError().finish())
-    } else {
-        Ok(HttpResponse::Ok().json(user_nicknames))
-    }
+    Ok(HttpResponse::Ok().json(user_nicknames))
 }
 
 pub async fn get(
-    redis: web::Data<Addr<RedisActor>>,
+    store: web::Data<dyn Store>,
     user_id: web::Path<String>,
     req: HttpRequest,
-) -> Result<HttpResponse, AWError> {
+) -> Result<HttpResponse, ApiError> {
     let user_id = user_id.into_inner();
 
-    let domain = format!("user:{}", user_id);
-
-    let get = redis.send(Command(resp_array!["GET", &domain]));
-    let auth = check_auth(&redis,&user_id,&req.headers());
+    let get = store::get_slice(&user_id, "user", &store);
+    let auth = check_auth(&store,&user_id,req.headers());
 
     let (res, is_auth) = join(get, auth).await; 
     
-    if let Ok(auth) = is_auth {
-        if !auth {
-            return Ok(HttpResponse::Unauthorized().finish())
-        }
-    } else {
-        return Ok(HttpResponse::Unauthorized().finish())
+    if !is_auth? {
+        return Err(ApiError::Unauthorized)
     }
     
     match res? {
-        Ok(RespValue::BulkString(x)) => Ok(HttpResponse::Ok().body(x)),
-        _ => Ok(HttpResponse::InternalServerError().finish()),
+        Some(x) => Ok(HttpResponse::Ok().content_type("application/json").body(x)),
+        None => Err(ApiError::not_found("user", &user_id)),
     }
 }
 
+/// adds a verified user and hands back a session for them, skipping the
+/// mail. admin only, whoever calls it gets into the account
 pub async fn force_add(
-    redis: web::Data<Addr<RedisActor>>,
+    store: web::Data<dyn Store>,
     user: web::Json<PartialUser>,
-) -> Result<HttpResponse, AWError> {
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
+    auth.require_admin()?;
+
     let temp_code = generate_temp_code(&user);
     let temp_code_domain = format!("temp_code:{}",&temp_code);
     let mut user: User = user.into_inner().into();
     user.is_verified = true;
-    let access_token = generate_access_token(&user);
-    let access_token_domain = format!("access_token:{}", &access_token);
 
-    let set_user = redis_add(user.to_owned(), &redis); 
+    let user_id = user.id();
 
-    let set_at = redis.send(Command(resp_array!["SET", &access_token_domain, &user.id()]));
-    let set_tc = redis.send(Command(resp_array!["SET", &temp_code_domain, &user.id()]));
+    let set_user = store::add(user.to_owned(), &store); 
 
-    let (user_add, _) = join(set_user, join(set_tc, set_at)).await;
+    let set_at = create_session(&store, &user_id);
+    let set_tc = store.set(&temp_code_domain, user_id.as_bytes());
 
-    match user_add {
-        true => Ok(HttpResponse::Ok().json((&user, access_token))),
-        false => Ok(HttpResponse::InternalServerError().finish()),
-    }
+    let (user_add, (tc_add, access_token)) = join(set_user, join(set_tc, set_at)).await;
+    user_add.and(tc_add)?;
+
+    emit(&store, None, EventData::UserAdded { user_id }).await;
+
+    Ok(HttpResponse::Ok().json((&user, access_token?)))
 }
 
 pub async fn sign_up(
-    redis: web::Data<Addr<RedisActor>>,
+    store: web::Data<dyn Store>,
     p_user: web::Json<PartialUser>,
-) -> Result<HttpResponse, AWError> {
+) -> Result<HttpResponse, ApiError> {
     let p_user = p_user.into_inner();
 
     let temp_code = generate_temp_code(&p_user);
@@ -115,129 +106,197 @@ pub async fn sign_up(
 
     // we need to delete the previous temp code if this email was already
     // registered, this endpoint effectively works as 'change nickname'
-    let get_current_user = redis.send(Command(resp_array!["GET", &user.domain()])).await?;
+    let get_current_user = store::get::<User>(&user.id(), &store).await?;
 
-    if let Ok(RespValue::BulkString(x)) = get_current_user {
-        let prev_user:User = serde_json::from_slice(&x).unwrap();
+    if let Some(prev_user) = get_current_user {
         let prev_partial_user = PartialUser{ 
             nickname: prev_user.nickname,
             email:email.to_string()
         };
         let prev_temp_code = generate_temp_code(&prev_partial_user);
         let prev_temp_code_domain = format!("temp_code:{}", &prev_temp_code);
-        let _del_prev = redis.send(Command(resp_array!["DEL", &prev_temp_code_domain])).await?;
+        let _del_prev = store.delete(&prev_temp_code_domain).await?;
     }
      
-    let email = compose_temp_code_mail(&user, &email, &temp_code);
+    let email = compose_temp_code_mail(&user, email, &temp_code);
     let send = async_send_mail(email);
-    let temp = redis.send(Command(resp_array!["SET", &temp_code_domain, &user.id()]));
-    let expire = redis.send(Command(resp_array!["EXPIRE", &temp_code_domain, "1800"]));
+    let user_id = user.id();
+    let temp = store.set_expiring(&temp_code_domain, user_id.as_bytes(), 1800);
 
-    let user_add = redis_add(user, &redis);
+    let user_add = store::add(user, &store);
 
-    let redis = join(user_add, join(temp, expire));
+    let db = join(user_add, temp);
 
-    let ((add, _tokens), _sendmail) = join(redis, send).await;
+    let ((add, temp), _sendmail) = join(db, send).await;
+    add.and(temp)?;
 
-    match add {
-        true => Ok(HttpResponse::Ok().body("email was sent with temp code")),
-        false => Ok(HttpResponse::InternalServerError().finish()),
-    }
+    emit(&store, None, EventData::UserSignedUp { user_id: user_id.to_owned() }).await;
+
+    Ok(HttpResponse::Ok().body("email was sent with temp code"))
 }
 
 pub async fn verify_temp_code(
-    redis: web::Data<Addr<RedisActor>>,
+    store: web::Data<dyn Store>,
     path: web::Path<(String, String)>, // user_id and temp
-) -> Result<HttpResponse, AWError> {
+) -> Result<HttpResponse, ApiError> {
     let (user_id, temp_code) = path.into_inner();
 
-    let domain = format!("user:{}", user_id);
     let code_domain = format!("temp_code:{}", &temp_code);
 
-    let get = redis.send(Command(resp_array!["GET", &domain]));
-    let temp = redis.send(Command(resp_array!["GET",&code_domain]));
+    let get = store::get::<User>(&user_id, &store);
+    let temp = store.get(&code_domain);
 
     let (user, uid_from_temp) = join(get, temp).await;
 
     let uid: String = match uid_from_temp? {
-        Ok(RespValue::BulkString(x)) => String::from_utf8(x).unwrap(),
-        _ => return Ok(HttpResponse::Unauthorized().finish())
+        Some(x) => String::from_utf8(x)
+            .map_err(|_| StoreError::Corrupt(format!("{} is not a user id", code_domain)))?,
+        None => return Err(ApiError::Unauthorized)
     };
 
-    let mut user: User = match user? {
-        Ok(RespValue::BulkString(x)) => {
-            serde_json::from_slice(&x).expect("user should be deserializable")
-        }
-        _ => return Ok(HttpResponse::InternalServerError().finish()),
-    };
+    let mut user: User = user?.ok_or_else(|| ApiError::not_found("user", &user_id))?;
 
     if user.id() == uid {
-        let access_token = generate_access_token(&user);
-        let token_domain = format!("access_token:{}",&access_token);
-        let set_token = redis.send(Command(resp_array!["SET", &token_domain, &user.id()]));
+        let user_id = user.id();
+        let set_token = create_session(&store, &user_id);
         user.is_verified = true;
 
-        let set = redis.send(Command(resp_array!["SET", &domain, user.json()]));
+        let (domain, user_json) = (user.domain(), user.json());
+        let set = store.set(&domain, user_json.as_bytes());
 
-        let (set, _set_token) = join(set, set_token).await;
+        let (set, access_token) = join(set, set_token).await;
+        set?;
 
-        match set? {
-            Ok(RespValue::SimpleString(x)) if x == "OK" => Ok(HttpResponse::Ok().body(access_token)),
-            _=> Ok(HttpResponse::InternalServerError().body("unable to set access token"))
-        }
+        emit(&store, Some(&user_id), EventData::UserVerified { user_id: user_id.to_owned() }).await;
+
+        Ok(HttpResponse::Ok().body(access_token?))
     } else {
-        Ok(HttpResponse::Unauthorized().body("invalid user_id, temp_code pair"))
+        // invalid user_id, temp_code pair
+        Err(ApiError::Unauthorized)
     }
 }
 
 pub async fn verify_auth_code(
-    redis: web::Data<Addr<RedisActor>>,
+    store: web::Data<dyn Store>,
     user_id: web::Path<String>,
     req: HttpRequest,
-    )-> Result<HttpResponse, AWError> {
+    )-> Result<HttpResponse, ApiError> {
 
     let user_id = user_id.into_inner();
     
-    let is_valid = check_auth(&redis,&user_id, req.headers()).await?;
+    let is_valid = check_auth(&store,&user_id, req.headers()).await?;
     
     match is_valid {
         true => Ok(HttpResponse::Ok().body("yes")),
-        false => Ok(HttpResponse::Unauthorized().finish())
+        false => Err(ApiError::Unauthorized)
     }
 }
 
 pub async fn delete(
-    redis: web::Data<Addr<RedisActor>>,
+    store: web::Data<dyn Store>,
     user_id: web::Path<String>,
     req: web::HttpRequest, 
-) -> Result<HttpResponse, AWError> {
+) -> Result<HttpResponse, ApiError> {
     
     let user_id = user_id.into_inner();
     let headers = req.headers();
 
-    let auth = check_auth(&redis, &user_id, &headers).await;
+    if !check_auth(&store, &user_id, headers).await? {
+        return Err(ApiError::Unauthorized);
+    }
+
+    let user: User = store::get(&user_id, &store)
+        .await?
+        .ok_or_else(|| ApiError::not_found("user", &user_id))?;
+
+    revoke_all_sessions(&store, &user_id).await?;
 
-    if let Ok(verified) = auth {
-        if !verified {
-            return Ok(HttpResponse::Unauthorized().finish());
+    match store::delete(user, &store).await? {
+        true => {
+            emit(&store, Some(&user_id), EventData::UserDeleted { user_id: user_id.to_owned() }).await;
+            Ok(HttpResponse::Ok().body("deleted user"))
         }
-    } else {
-        return Ok(HttpResponse::Unauthorized().finish());
+        false => Err(ApiError::not_found("user", &user_id)),
     }
+}
 
-    let user_json = redis_get_slice(&user_id, "user", &redis).await;
+pub async fn list_user_sessions(
+    store: web::Data<dyn Store>,
+    user_id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user_id.into_inner();
 
-    if user_json.is_none() {
-        return Ok(HttpResponse::NoContent().finish())
+    if !check_auth(&store, &user_id, req.headers()).await? {
+        return Err(ApiError::Unauthorized);
     }
 
-    let user: User = serde_json::from_slice(&user_json.unwrap())
-        .expect("User should be able to Serialize");
+    let sessions = list_sessions(&store, &user_id).await?;
 
-    let del = redis_delete(user, &redis).await;
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+/// revokes the token the request was made with
+pub async fn logout(
+    store: web::Data<dyn Store>,
+    user_id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user_id.into_inner();
+
+    if !check_auth(&store, &user_id, req.headers()).await? {
+        return Err(ApiError::Unauthorized);
+    }
+
+    let id = bearer_token(req.headers()).map(session_id).unwrap_or_default();
+
+    match revoke_session(&store, &user_id, &id).await? {
+        true => {
+            let revoked = EventData::SessionRevoked { user_id: user_id.to_owned(), session_id: id };
+            emit(&store, Some(&user_id), revoked).await;
+            Ok(HttpResponse::Ok().body("logged out"))
+        }
+        // the master key has no session to end
+        false => Err(ApiError::not_found("session", &id)),
+    }
+}
+
+pub async fn revoke_user_session(
+    store: web::Data<dyn Store>,
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let (user_id, id) = path.into_inner();
 
-    match del {
-        true => Ok(HttpResponse::Ok().body("deleted user")),
-        false => Ok(HttpResponse::InternalServerError().finish()),
+    if !check_auth(&store, &user_id, req.headers()).await? {
+        return Err(ApiError::Unauthorized);
     }
+
+    match revoke_session(&store, &user_id, &id).await? {
+        true => {
+            let revoked = EventData::SessionRevoked { user_id: user_id.to_owned(), session_id: id };
+            emit(&store, Some(&user_id), revoked).await;
+            Ok(HttpResponse::Ok().body("revoked session"))
+        }
+        false => Err(ApiError::not_found("session", &id)),
+    }
+}
+
+pub async fn revoke_user_sessions(
+    store: web::Data<dyn Store>,
+    user_id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user_id.into_inner();
+
+    if !check_auth(&store, &user_id, req.headers()).await? {
+        return Err(ApiError::Unauthorized);
+    }
+
+    let revoked = revoke_all_sessions(&store, &user_id).await?;
+
+    let event = EventData::SessionsRevoked { user_id: user_id.to_owned(), count: revoked };
+    emit(&store, Some(&user_id), event).await;
+
+    Ok(HttpResponse::Ok().json(revoked))
 }
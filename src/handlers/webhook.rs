@@ -0,0 +1,247 @@
+use crate::{
+    auth::{generate_access_token, Auth},
+    error::ApiError,
+    handlers::topic::{require_role, topic_as},
+    model::{EventKind, Role, Settable, Topic, Webhook},
+    notify,
+    store::{self, Store, StoreError},
+};
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use futures::future::join;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeSet;
+
+#[derive(Deserialize)]
+pub struct NewWebhook {
+    url: String,
+    /// every event if left out
+    #[serde(default)]
+    events: BTreeSet<EventKind>,
+}
+
+// only http(s), anything else would have us post to who knows what. a
+// topic's owner is anybody, their webhooks also have to be public
+async fn check_url(url: &str, topic_id: Option<&str>) -> Result<(), ApiError> {
+    match url::Url::parse(url) {
+        Ok(x) if x.scheme() == "http" || x.scheme() == "https" => (),
+        _ => return Err(ApiError::InvalidRequest(format!("{} is not an http(s) url", url))),
+    }
+
+    match topic_id {
+        Some(_) => notify::check_target(url)
+            .await
+            .map(|_| ())
+            .map_err(ApiError::InvalidRequest),
+        None => Ok(()),
+    }
+}
+
+/// the secret is in the answer, and only here. it's what the receiver
+/// checks `X-Ornot-Signature` with
+async fn create(
+    topic_id: Option<String>,
+    new: NewWebhook,
+    store: &web::Data<dyn Store>,
+) -> Result<HttpResponse, ApiError> {
+    check_url(&new.url, topic_id.as_deref()).await?;
+
+    let secret = generate_access_token();
+    let webhook = Webhook::new(
+        generate_access_token(),
+        topic_id,
+        new.url,
+        new.events,
+        secret.to_owned(),
+        Utc::now().timestamp(),
+    );
+
+    let set = Webhook::set_of(webhook.topic_id.as_deref());
+    let (add, list) = join(
+        store.set(&webhook.domain(), webhook.json().as_bytes()),
+        store.add_member(&set, webhook.id.as_bytes()),
+    )
+    .await;
+    add.and(list)?;
+
+    let mut answer = json!(webhook.info());
+    answer["secret"] = json!(secret);
+
+    Ok(HttpResponse::Ok().json(answer))
+}
+
+async fn list_set(set: &str, store: &web::Data<dyn Store>) -> Result<HttpResponse, ApiError> {
+    let ids: Vec<String> = store
+        .members(set)
+        .await?
+        .into_iter()
+        .map(|x| String::from_utf8_lossy(&x).into_owned())
+        .collect();
+
+    let webhooks = store::get_all::<Webhook>(&ids, &Webhook::domain_prefix(), store).await?;
+    let infos: Vec<_> = webhooks.iter().map(|(_, x)| x.info()).collect();
+
+    Ok(HttpResponse::Ok().json(infos))
+}
+
+/// a webhook belongs to its topic's owners, one for every topic to admins
+async fn webhook_as(
+    webhook_id: &str,
+    store: &web::Data<dyn Store>,
+    auth: &Auth,
+) -> Result<Webhook, ApiError> {
+    let webhook: Webhook = store::get(webhook_id, store)
+        .await?
+        .ok_or_else(|| ApiError::not_found("webhook", webhook_id))?;
+
+    match &webhook.topic_id {
+        Some(topic_id) => {
+            let topic: Topic = store::get(topic_id, store)
+                .await?
+                .ok_or_else(|| ApiError::not_found("topic", topic_id))?;
+            require_role(&topic, auth, Role::Owner)?;
+        }
+        None => auth.require_admin()?,
+    }
+
+    Ok(webhook)
+}
+
+pub async fn register_for_topic(
+    store: web::Data<dyn Store>,
+    topic_id: web::Path<String>,
+    new: web::Json<NewWebhook>,
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
+    let topic_id = topic_id.into_inner();
+    topic_as(&topic_id, &store, &auth, Role::Owner).await?;
+
+    create(Some(topic_id), new.into_inner(), &store).await
+}
+
+pub async fn list_for_topic(
+    store: web::Data<dyn Store>,
+    topic_id: web::Path<String>,
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
+    let topic_id = topic_id.into_inner();
+    topic_as(&topic_id, &store, &auth, Role::Owner).await?;
+
+    list_set(&Webhook::set_of(Some(&topic_id)), &store).await
+}
+
+/// a webhook for every topic, the only ones that hear of new topics
+pub async fn register(
+    store: web::Data<dyn Store>,
+    new: web::Json<NewWebhook>,
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
+    auth.require_admin()?;
+
+    create(None, new.into_inner(), &store).await
+}
+
+pub async fn list(store: web::Data<dyn Store>, auth: Auth) -> Result<HttpResponse, ApiError> {
+    auth.require_admin()?;
+
+    list_set(&Webhook::set_of(None), &store).await
+}
+
+pub async fn delete(
+    store: web::Data<dyn Store>,
+    webhook_id: web::Path<String>,
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
+    let webhook = webhook_as(&webhook_id, &store, &auth).await?;
+
+    let set = Webhook::set_of(webhook.topic_id.as_deref());
+    let (del, unlist) = join(
+        store.delete(&webhook.domain()),
+        store.remove_member(&set, webhook.id.as_bytes()),
+    )
+    .await;
+    unlist?;
+    del?;
+
+    Ok(HttpResponse::Ok().json("deleted"))
+}
+
+/// the webhooks of a topic that is going away. a topic with the same
+/// title and description gets the same id, it mustn't inherit them
+pub(crate) async fn delete_for_topic(
+    topic_id: &str,
+    store: &web::Data<dyn Store>,
+) -> Result<(), StoreError> {
+    let set = Webhook::set_of(Some(topic_id));
+
+    for id in store.members(&set).await? {
+        let webhook_id = String::from_utf8_lossy(&id).into_owned();
+        store
+            .delete(&format!("{}:{}", Webhook::domain_prefix(), webhook_id))
+            .await?;
+        store.remove_member(&set, &id).await?;
+    }
+
+    Ok(())
+}
+
+/// the last attempts at delivering, newest last
+pub async fn deliveries(
+    store: web::Data<dyn Store>,
+    webhook_id: web::Path<String>,
+    auth: Auth,
+) -> Result<HttpResponse, ApiError> {
+    let webhook = webhook_as(&webhook_id, &store, &auth).await?;
+
+    Ok(HttpResponse::Ok().json(webhook.deliveries()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn add(store: &web::Data<dyn Store>, topic_id: Option<&str>, url: &str) -> String {
+        let new = NewWebhook {
+            url: url.to_string(),
+            events: BTreeSet::new(),
+        };
+        create(topic_id.map(str::to_string), new, store).await.unwrap();
+
+        let set = Webhook::set_of(topic_id);
+        let ids = store.members(&set).await.unwrap();
+        String::from_utf8(ids.last().unwrap().to_owned()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn topic_webhooks_must_be_public() {
+        let store = store::memory();
+
+        let refused = create(
+            Some("topic".to_string()),
+            NewWebhook {
+                url: "http://169.254.169.254/latest".to_string(),
+                events: BTreeSet::new(),
+            },
+            &store,
+        )
+        .await;
+        assert!(matches!(refused, Err(ApiError::InvalidRequest(_))));
+
+        // admins may point theirs anywhere
+        add(&store, None, "http://127.0.0.1/hook").await;
+    }
+
+    #[tokio::test]
+    async fn deleting_a_topic_takes_its_webhooks() {
+        let store = store::memory();
+        let gone = add(&store, Some("topic"), "http://1.1.1.1/hook").await;
+        let kept = add(&store, None, "http://127.0.0.1/hook").await;
+
+        delete_for_topic("topic", &store).await.unwrap();
+
+        assert!(store.members(&Webhook::set_of(Some("topic"))).await.unwrap().is_empty());
+        assert!(store::get::<Webhook>(&gone, &store).await.unwrap().is_none());
+        assert!(store::get::<Webhook>(&kept, &store).await.unwrap().is_some());
+    }
+}
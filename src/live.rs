@@ -0,0 +1,291 @@
+use crate::model::{Settable, Topic};
+use actix::{Message, Recipient};
+use actix_web::web::Bytes;
+use futures::channel::mpsc::{channel, Receiver, Sender};
+use serde_json::json;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// proxies hang up on connections that stay quiet for too long
+const HEARTBEAT: Duration = Duration::from_secs(15);
+/// events a client may fall behind by, over server-sent events or in a
+/// websocket's mailbox. one that doesn't keep up is hung up on rather than
+/// buffered for without end, it can reconnect for the result
+pub const CLIENT_BUFFER: usize = 64;
+
+/// a json message for a websocket session to pass on as it is
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Push(pub String);
+
+// a websocket session in a topic
+struct Seat {
+    id: u64,
+    user_id: String,
+    session: Recipient<Push>,
+}
+
+/// the clients following each topic, over server-sent events or in a
+/// websocket session. one is shared by all workers, so a change made in
+/// any of them reaches everyone.
+#[derive(Default)]
+pub struct Live {
+    clients: Mutex<HashMap<String, Vec<Sender<Bytes>>>>,
+    seats: Mutex<HashMap<String, Vec<Seat>>>,
+    next_seat: Mutex<u64>,
+}
+
+impl Live {
+    /// a stream of events for `topic`, starting with its current result.
+    /// it ends when the client falls `CLIENT_BUFFER` events behind
+    pub fn subscribe(&self, topic: &Topic) -> Receiver<Bytes> {
+        let (mut tx, rx) = channel(CLIENT_BUFFER);
+        // only fails if rx is gone or full, which it isn't yet
+        let _ = tx.try_send(event(topic));
+
+        self.clients
+            .lock()
+            .unwrap()
+            .entry(topic.id())
+            .or_default()
+            .push(tx);
+
+        rx
+    }
+
+    /// seats a websocket session of `user_id` in `topic` and sends it the
+    /// current result. everyone in the topic learns who is there now.
+    /// returns the seat to `leave` with
+    pub fn join(&self, topic: &Topic, user_id: &str, session: Recipient<Push>) -> u64 {
+        let id = {
+            let mut next = self.next_seat.lock().unwrap();
+            *next += 1;
+            *next
+        };
+
+        // only fails if the session is gone already, it won't be seated for long
+        let _ = session.try_send(Push(result_message(topic)));
+
+        let topic_id = topic.id();
+        self.seats
+            .lock()
+            .unwrap()
+            .entry(topic_id.to_owned())
+            .or_default()
+            .push(Seat {
+                id,
+                user_id: user_id.to_owned(),
+                session,
+            });
+        self.presence(&topic_id);
+
+        id
+    }
+
+    /// false once the seat was given up for falling behind, the session
+    /// should hang up then
+    pub fn seated(&self, topic_id: &str, seat: u64) -> bool {
+        self.seats
+            .lock()
+            .unwrap()
+            .get(topic_id)
+            .is_some_and(|seats| seats.iter().any(|x| x.id == seat))
+    }
+
+    pub fn leave(&self, topic_id: &str, seat: u64) {
+        if let Some(seats) = self.seats.lock().unwrap().get_mut(topic_id) {
+            seats.retain(|x| x.id != seat);
+        }
+        self.presence(topic_id);
+    }
+
+    /// sends the result of `topic` to everyone following it
+    pub fn publish(&self, topic: &Topic) {
+        let topic_id = topic.id();
+        self.send(&topic_id, event(topic));
+        self.push(&topic_id, result_message(topic));
+    }
+
+    /// tells everyone following `topic` which status it is in now
+    pub fn publish_status(&self, topic: &Topic) {
+        let topic_id = topic.id();
+        let message = status_message(topic);
+        self.send(&topic_id, Bytes::from(format!("event: status\ndata: {}\n\n", message)));
+        self.push(&topic_id, message);
+    }
+
+    fn send(&self, topic_id: &str, message: Bytes) {
+        let mut clients = self.clients.lock().unwrap();
+
+        if let Some(senders) = clients.get_mut(topic_id) {
+            senders.retain_mut(|tx| tx.try_send(message.clone()).is_ok());
+            if senders.is_empty() {
+                clients.remove(topic_id);
+            }
+        }
+    }
+
+    // who is in a topic, counting each user once however many tabs they have
+    fn presence(&self, topic_id: &str) {
+        let users: BTreeSet<String> = match self.seats.lock().unwrap().get(topic_id) {
+            Some(seats) => seats.iter().map(|x| x.user_id.to_owned()).collect(),
+            None => return,
+        };
+
+        let message = json!({ "type": "presence", "users": users });
+        self.push(topic_id, message.to_string());
+    }
+
+    fn push(&self, topic_id: &str, message: String) {
+        let mut seats = self.seats.lock().unwrap();
+
+        if let Some(x) = seats.get_mut(topic_id) {
+            // a full mailbox is a session that falls behind, same as a
+            // closed one it loses its seat
+            x.retain(|seat| seat.session.try_send(Push(message.clone())).is_ok());
+            if x.is_empty() {
+                seats.remove(topic_id);
+            }
+        }
+    }
+
+    /// a comment to every client now and then. clients that went away or
+    /// fell behind are dropped on the way
+    pub async fn heartbeat(&self) {
+        let mut interval = tokio::time::interval(HEARTBEAT);
+
+        loop {
+            interval.tick().await;
+
+            let ping = Bytes::from_static(b": ping\n\n");
+            let mut clients = self.clients.lock().unwrap();
+            for senders in clients.values_mut() {
+                senders.retain_mut(|tx| tx.try_send(ping.clone()).is_ok());
+            }
+            clients.retain(|_, senders| !senders.is_empty());
+        }
+    }
+}
+
+fn result_message(topic: &Topic) -> String {
+    json!({
+        "type": "result",
+        "setting_hash": topic.setting_hash,
+        "result": topic.result_view(),
+    })
+    .to_string()
+}
+
+fn status_message(topic: &Topic) -> String {
+    json!({
+        "type": "status",
+        "status": topic.status,
+        "closed_at": topic.closed_at(),
+    })
+    .to_string()
+}
+
+// the setting hash doubles as the event id, a client can tell whether it
+// missed something
+fn event(topic: &Topic) -> Bytes {
+    let data = json!({
+        "setting_hash": topic.setting_hash,
+        "result": topic.result_view(),
+    });
+
+    Bytes::from(format!(
+        "event: result\nid: {}\ndata: {}\n\n",
+        topic.setting_hash, data
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::PartialTopic;
+    use futures::StreamExt;
+
+    fn topic() -> Topic {
+        serde_json::from_value::<PartialTopic>(json!({"title": "t", "description": "d"}))
+            .unwrap()
+            .into()
+    }
+
+    fn followers(live: &Live, topic: &Topic) -> usize {
+        live.clients.lock().unwrap().get(&topic.id()).map_or(0, Vec::len)
+    }
+
+    #[tokio::test]
+    async fn clients_get_the_result_then_every_event() {
+        let live = Live::default();
+        let topic = topic();
+        let mut rx = live.subscribe(&topic);
+
+        live.publish_status(&topic);
+
+        assert!(rx.next().await.unwrap().starts_with(b"event: result\n"));
+        assert!(rx.next().await.unwrap().starts_with(b"event: status\n"));
+        assert_eq!(followers(&live, &topic), 1);
+    }
+
+    #[tokio::test]
+    async fn a_client_that_falls_behind_is_hung_up_on() {
+        let live = Live::default();
+        let topic = topic();
+        let slow = live.subscribe(&topic);
+        let mut fast = live.subscribe(&topic);
+
+        for _ in 0..CLIENT_BUFFER * 2 {
+            live.publish(&topic);
+            fast.next().await.unwrap();
+        }
+
+        assert_eq!(followers(&live, &topic), 1);
+        // what was buffered still arrives, then the stream ends
+        assert_eq!(slow.collect::<Vec<Bytes>>().await.len(), CLIENT_BUFFER + 1);
+    }
+
+    struct Session;
+
+    impl actix::Actor for Session {
+        type Context = actix::Context<Self>;
+    }
+
+    impl actix::Handler<Push> for Session {
+        type Result = ();
+
+        fn handle(&mut self, _: Push, _: &mut Self::Context) {}
+    }
+
+    #[test]
+    fn a_session_that_falls_behind_loses_its_seat() {
+        actix::System::new("test").block_on(async {
+            let live = Live::default();
+            let topic = topic();
+
+            // never gets to read its mailbox, nothing is awaited
+            let session = actix::Actor::create(|ctx: &mut actix::Context<Session>| {
+                ctx.set_mailbox_capacity(4);
+                Session
+            });
+            let seat = live.join(&topic, "alice", session.recipient());
+            assert!(live.seated(&topic.id(), seat));
+
+            for _ in 0..10 {
+                live.publish(&topic);
+            }
+            assert!(!live.seated(&topic.id(), seat));
+        });
+    }
+
+    #[tokio::test]
+    async fn clients_that_went_away_are_dropped() {
+        let live = Live::default();
+        let topic = topic();
+        drop(live.subscribe(&topic));
+
+        live.publish(&topic);
+        assert!(live.clients.lock().unwrap().is_empty());
+    }
+}
@@ -1,14 +1,26 @@
 mod auth;
+mod chart;
+mod error;
+mod export;
 mod handlers;
+mod live;
 mod model;
-mod redis_helper;
+mod notify;
+mod results;
+mod scheduler;
 mod send_mail;
+mod store;
 
+
+use actix::Actor;
 use actix_cors::Cors;
-use actix_redis::RedisActor;
 use actix_web::{middleware, web, App, HttpServer};
-use dotenv;
 use std::env;
+use std::sync::Arc;
+use live::Live;
+use results::ResultCache;
+use scheduler::Scheduler;
+use store::{MemoryStore, RedisStore, SqliteStore, Store};
 
 use handlers::*;
 
@@ -21,20 +33,52 @@ async fn main() -> std::io::Result<()> {
     );
     env_logger::init();
 
-    HttpServer::new(|| {
-        let address = format!(
-            "{}:{}",
-            env::var("REDIS_ADDR").unwrap(),
-            env::var("REDIS_PORT").unwrap()
-        );
+    // 'redis' (default), 'sqlite' or 'memory'. the latter does not survive
+    // restarts but is shared across workers
+    let backend = env::var("STORE_BACKEND").unwrap_or_else(|_| "redis".to_string());
+    let memory = MemoryStore::new();
+    let sqlite = match backend.as_str() {
+        "sqlite" => {
+            let path = env::var("SQLITE_PATH").unwrap_or_else(|_| "ornot.sqlite".to_string());
+            Some(SqliteStore::open(&path).expect("could not open sqlite database"))
+        }
+        _ => None,
+    };
+
+    let open_store = move || -> Arc<dyn Store> {
+        match (backend.as_str(), &sqlite) {
+            ("sqlite", Some(sqlite)) => Arc::new(sqlite.clone()),
+            ("memory", _) => Arc::new(memory.clone()),
+            _ => {
+                let address = format!(
+                    "{}:{}",
+                    env::var("REDIS_ADDR").unwrap(),
+                    env::var("REDIS_PORT").unwrap()
+                );
+                Arc::new(RedisStore::start(&address))
+            }
+        }
+    };
+
+    // outside the workers, so all of them reach the same clients
+    let live = web::Data::new(Live::default());
+    let heartbeat = live.clone();
+    actix_web::rt::spawn(async move { heartbeat.heartbeat().await });
+
+    Scheduler::new(web::Data::from(open_store()), live.clone()).start();
+    let results = web::Data::new(ResultCache::from_env());
 
-        let redis_addr = RedisActor::start(&address);
+    HttpServer::new(move || {
+        let store = open_store();
 
         // TODO: change this
         let cors = Cors::permissive();
 
         App::new()
-            .data(redis_addr)
+            .app_data(web::Data::from(store))
+            .app_data(live.clone())
+            .app_data(results.clone())
+            .app_data(web::JsonConfig::default().error_handler(error::json_error))
             .wrap(middleware::Logger::default())
             .wrap(cors)
             // user
@@ -51,6 +95,19 @@ async fn main() -> std::io::Result<()> {
                 web::resource("/api/v1/user/{user_id}/check")
                     .route(web::get().to(user::verify_auth_code)),
             )
+            .service(
+                web::resource("/api/v1/user/{user_id}/logout")
+                    .route(web::post().to(user::logout)),
+            )
+            .service(
+                web::resource("/api/v1/user/{user_id}/sessions")
+                    .route(web::get().to(user::list_user_sessions))
+                    .route(web::delete().to(user::revoke_user_sessions)),
+            )
+            .service(
+                web::resource("/api/v1/user/{user_id}/session/{session_id}")
+                    .route(web::delete().to(user::revoke_user_session)),
+            )
             .service(
                 web::resource("/api/v1/user/{user_id}")
                     .route(web::get().to(user::get))
@@ -78,15 +135,121 @@ async fn main() -> std::io::Result<()> {
                     .route(web::post().to(topic::add_user))
                     .route(web::delete().to(topic::remove_user)),
             )
+            .service(
+                web::resource("/api/v1/topic/{topic_id}/events")
+                    .route(web::get().to(topic::events)),
+            )
+            .service(
+                web::resource("/api/v1/topic/{topic_id}/session")
+                    .route(web::get().to(session::join)),
+            )
+            .service(
+                web::resource("/api/v1/topic/{topic_id}/session/ticket")
+                    .route(web::post().to(session::ticket)),
+            )
+            .service(
+                web::resource("/api/v1/topic/{topic_id}/status")
+                    .route(web::put().to(topic::set_status)),
+            )
+            .service(
+                web::resource("/api/v1/topic/{topic_id}/schedule")
+                    .route(web::put().to(topic::set_schedule)),
+            )
+            .service(
+                web::resource("/api/v1/topic/{topic_id}/history")
+                    .route(web::get().to(topic::history)),
+            )
+            .service(
+                web::resource("/api/v1/topic/{topic_id}/history/{setting_hash}")
+                    .route(web::get().to(topic::at_setting)),
+            )
+            .service(
+                web::resource("/api/v1/topic/{topic_id}/audit")
+                    .route(web::get().to(topic::audit)),
+            )
+            .service(
+                web::resource("/api/v1/topic/{topic_id}/audit/verify")
+                    .route(web::get().to(topic::verify)),
+            )
+            .service(
+                web::resource("/api/v1/topic/{topic_id}/proof/{user_id}")
+                    .route(web::get().to(topic::ballot_proof)),
+            )
+            .service(
+                web::resource("/api/v1/topic/{topic_id}/graph")
+                    .route(web::get().to(topic::graph)),
+            )
+            .service(
+                web::resource("/api/v1/topic/{topic_id}/chart/{kind}.svg")
+                    .route(web::get().to(result::chart)),
+            )
+            .service(
+                web::resource("/api/v1/topic/{topic_id}/result.{format}")
+                    .route(web::get().to(result::export)),
+            )
+            .service(
+                web::resource("/api/v1/topic/{topic_id}/simulate")
+                    .route(web::post().to(topic::simulate)),
+            )
+            .service(
+                web::resource("/api/v1/topic/{topic_id}/method")
+                    .route(web::put().to(topic::set_method)),
+            )
+            .service(
+                web::resource("/api/v1/topic/{topic_id}/tally")
+                    .route(web::get().to(topic::tally)),
+            )
+            .service(
+                web::resource("/api/v1/topic/{topic_id}/roles")
+                    .route(web::get().to(topic::list_roles)),
+            )
+            .service(
+                web::resource("/api/v1/topic/{topic_id}/role/{user_id}")
+                    .route(web::put().to(topic::grant_role))
+                    .route(web::delete().to(topic::revoke_role)),
+            )
+            .service(
+                web::resource("/api/v1/topic/{topic_id}/webhooks")
+                    .route(web::get().to(webhook::list_for_topic))
+                    .route(web::post().to(webhook::register_for_topic)),
+            )
             .service(
                 web::resource("/api/v1/topic/{topic_id}/new_plan")
                     .route(web::post().to(topic::add_plan)),
             )
+            // webhook
+            .service(
+                web::resource("/api/v1/webhooks")
+                    .route(web::get().to(webhook::list))
+                    .route(web::post().to(webhook::register)),
+            )
+            .service(
+                web::resource("/api/v1/webhook/{webhook_id}")
+                    .route(web::delete().to(webhook::delete)),
+            )
+            .service(
+                web::resource("/api/v1/webhook/{webhook_id}/deliveries")
+                    .route(web::get().to(webhook::deliveries)),
+            )
+            // event stream
+            .service(
+                web::resource("/api/v1/stream/group/{group}")
+                    .route(web::put().to(stream::create_group))
+                    .route(web::get().to(stream::read)),
+            )
+            .service(
+                web::resource("/api/v1/stream/group/{group}/ack")
+                    .route(web::post().to(stream::ack)),
+            )
             // plan
             .service(web::resource("api/v1/plan/{plan_id}").route(web::get().to(plan::get)))
             .service(web::resource("api/v1/plan").route(web::put().to(plan::put)))
             // setting and calculate
             .service(web::resource("api/v1/setting/{setting_id}").route(web::get().to(get_setting)))
+            .service(
+                web::resource("api/v1/setting/{from_hash}/diff/{to_hash}")
+                    .route(web::get().to(diff_settings)),
+            )
             .service(web::resource("api/v1/calculate_raw").route(web::post().to(calculate_setting)))
             // helper
             .service(web::resource("api/v1/nuclear").route(web::delete().to(nuclear)))
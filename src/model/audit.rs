@@ -0,0 +1,365 @@
+use crate::model::{Change, Settable};
+use bs58::encode;
+use liq::{PollResult, Setting};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+// what the first entry of every chain points back to
+const GENESIS: &str = "0";
+
+/// a change to a topic's setting, with everything needed to replay it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    /// the setting as it was when the log started, always the first entry
+    Genesis {
+        setting: serde_json::Value,
+    },
+    Vote {
+        voter: String,
+        vote: BTreeMap<String, f64>,
+    },
+    AddPlan {
+        plan_id: String,
+    },
+    RemovePlan {
+        plan_id: String,
+    },
+    AddVoter {
+        voter: String,
+    },
+    RemoveVoter {
+        voter: String,
+    },
+}
+
+impl Action {
+    /// the short form kept in the topic's history, the genesis changes nothing
+    pub fn change(&self) -> Option<Change> {
+        let change = match self {
+            Action::Genesis { .. } => return None,
+            Action::Vote { voter, .. } => Change::Vote { voter: voter.to_owned() },
+            Action::AddPlan { plan_id } => Change::AddPlan { plan_id: plan_id.to_owned() },
+            Action::RemovePlan { plan_id } => Change::RemovePlan { plan_id: plan_id.to_owned() },
+            Action::AddVoter { voter } => Change::AddVoter { voter: voter.to_owned() },
+            Action::RemoveVoter { voter } => Change::RemoveVoter { voter: voter.to_owned() },
+        };
+
+        Some(change)
+    }
+
+    pub fn apply(&self, setting: &mut Setting) {
+        match self {
+            // a snapshot that doesn't parse leaves the setting as it is,
+            // the replay won't match the recorded hash then
+            Action::Genesis { setting: snapshot } => {
+                if let Ok(x) = serde_json::from_value(snapshot.to_owned()) {
+                    *setting = x;
+                }
+            }
+            Action::Vote { voter, vote } => setting.overwrite_vote(voter, vote.to_owned()),
+            Action::AddPlan { plan_id } => setting.add_plan(plan_id),
+            Action::RemovePlan { plan_id } => setting.delete_plan(plan_id),
+            Action::AddVoter { voter } => setting.add_voter(voter),
+            Action::RemoveVoter { voter } => setting.delete_voter(voter),
+        }
+    }
+}
+
+/// the last entry of a topic's audit log, kept on the topic itself so
+/// appending is ordered by the same compare-and-set as the change
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditHead {
+    pub seq: u64,
+    pub hash: String,
+}
+
+impl Default for AuditHead {
+    fn default() -> Self {
+        Self {
+            seq: 0,
+            hash: GENESIS.to_string(),
+        }
+    }
+}
+
+/// one line of the audit log, stored under `audit:{topic_id}:{seq}` and
+/// never written again. `hash` covers every other field, `prev` included,
+/// so changing any entry breaks every one after it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub topic_id: String,
+    pub seq: u64,
+    pub at: i64,
+    // none for admins
+    pub by: Option<String>,
+    pub action: Action,
+    pub setting_hash: String,
+    pub prev: String,
+    pub hash: String,
+}
+
+// the hashed part of an entry, field order is part of the format
+#[derive(Serialize)]
+struct Unsealed<'a> {
+    topic_id: &'a str,
+    seq: u64,
+    at: i64,
+    by: &'a Option<String>,
+    action: &'a Action,
+    setting_hash: &'a str,
+    prev: &'a str,
+}
+
+impl AuditEntry {
+    /// the entry following `head`, which is moved on to it
+    pub fn append(
+        head: &mut AuditHead,
+        topic_id: &str,
+        at: i64,
+        by: Option<&str>,
+        action: Action,
+        setting_hash: &str,
+    ) -> Self {
+        let mut entry = Self {
+            topic_id: topic_id.to_string(),
+            seq: head.seq + 1,
+            at,
+            by: by.map(str::to_string),
+            action,
+            setting_hash: setting_hash.to_string(),
+            prev: head.hash.to_owned(),
+            hash: String::new(),
+        };
+
+        entry.hash = entry.seal();
+        head.seq = entry.seq;
+        head.hash = entry.hash.to_owned();
+
+        entry
+    }
+
+    fn seal(&self) -> String {
+        let unsealed = serde_json::to_vec(&Unsealed {
+            topic_id: &self.topic_id,
+            seq: self.seq,
+            at: self.at,
+            by: &self.by,
+            action: &self.action,
+            setting_hash: &self.setting_hash,
+            prev: &self.prev,
+        })
+        .expect("AuditEntry should be Serializable");
+
+        encode(Sha256::digest(&unsealed)).into_string()
+    }
+}
+
+impl Settable for AuditEntry {
+    fn domain_prefix() -> String {
+        String::from("audit")
+    }
+
+    fn id(&self) -> String {
+        format!("{}:{}", self.topic_id, self.seq)
+    }
+
+    fn list_item(&self) -> String {
+        self.id()
+    }
+}
+
+/// what `verify_audit` found. `errors` is empty when the log is intact
+#[derive(Debug, Serialize)]
+pub struct AuditReport {
+    pub entries: usize,
+    pub valid: bool,
+    pub errors: Vec<String>,
+    /// the setting hash and result the log replays to
+    pub setting_hash: String,
+    pub result: PollResult,
+}
+
+/// walks the chain from the start, checking every link and hash, and
+/// replays the actions from the genesis snapshot every log starts with.
+/// `head` and `setting_hash` are what the topic currently claims.
+pub fn verify_audit(
+    entries: &[AuditEntry],
+    head: &AuditHead,
+    setting_hash: &str,
+) -> AuditReport {
+    let mut errors = Vec::new();
+    let mut setting = Setting::new();
+    let mut prev = GENESIS.to_string();
+
+    for (i, entry) in entries.iter().enumerate() {
+        let seq = i as u64 + 1;
+
+        if entry.seq != seq {
+            errors.push(format!("entry {} is missing, found {} instead", seq, entry.seq));
+        }
+        if entry.prev != prev {
+            errors.push(format!("entry {} does not point to the one before it", entry.seq));
+        }
+        if entry.seal() != entry.hash {
+            errors.push(format!("entry {} does not match its hash", entry.seq));
+        }
+        match (i, &entry.action) {
+            (0, Action::Genesis { .. }) => {}
+            (0, _) => errors.push(format!("entry {} is not a snapshot to start from", entry.seq)),
+            (_, Action::Genesis { .. }) => errors.push(format!("entry {} starts the log over", entry.seq)),
+            _ => {}
+        }
+
+        entry.action.apply(&mut setting);
+
+        if setting.based_hash() != entry.setting_hash {
+            errors.push(format!(
+                "entry {} replays to a different setting than it recorded",
+                entry.seq
+            ));
+        }
+
+        prev = entry.hash.to_owned();
+    }
+
+    if entries.len() as u64 != head.seq || prev != head.hash {
+        errors.push("the log does not end where the topic says it does".to_string());
+    }
+
+    let replayed = setting.based_hash();
+    if !entries.is_empty() && replayed != setting_hash {
+        errors.push("the log does not replay to the topic's current setting".to_string());
+    }
+
+    AuditReport {
+        entries: entries.len(),
+        valid: errors.is_empty(),
+        errors,
+        setting_hash: replayed,
+        result: setting.calculate(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{PartialTopic, Topic};
+    use crate::results::ResultCache;
+
+    fn topic() -> Topic {
+        let partial: PartialTopic =
+            serde_json::from_value(serde_json::json!({"title": "t", "description": "d"})).unwrap();
+        partial.into()
+    }
+
+    // the log of a topic as it would be stored, after `changes`
+    fn logged(topic: &mut Topic, changes: Vec<Action>) -> Vec<AuditEntry> {
+        let results = ResultCache::new(10);
+        let mut entries = Vec::new();
+
+        for action in changes {
+            action.apply_to(topic);
+            let entry = topic.commit(Some("alice"), action, &results).unwrap();
+            entries.extend(topic.genesis().cloned());
+            entries.push(entry);
+        }
+
+        entries
+    }
+
+    impl Action {
+        fn apply_to(&self, topic: &mut Topic) {
+            match self {
+                // `commit` writes that one by itself
+                Action::Genesis { .. } => {}
+                Action::AddPlan { plan_id } => topic.add_plan_id(plan_id),
+                Action::RemovePlan { plan_id } => topic.remove_plan_id(plan_id),
+                Action::AddVoter { voter } => topic.add_user(voter.to_owned()),
+                Action::RemoveVoter { voter } => topic.remove_user(voter.to_owned()),
+                Action::Vote { voter, vote } => topic.insert_vote(voter, vote.to_owned()),
+            }
+        }
+    }
+
+    fn changes() -> Vec<Action> {
+        let mut vote = BTreeMap::new();
+        vote.insert("bread".to_string(), 1.0);
+
+        vec![
+            Action::AddPlan { plan_id: "bread".into() },
+            Action::AddVoter { voter: "bob".into() },
+            Action::Vote { voter: "bob".into(), vote },
+        ]
+    }
+
+    #[test]
+    fn a_fresh_log_verifies() {
+        let mut topic = topic();
+        let entries = logged(&mut topic, changes());
+
+        assert_eq!(entries.len(), 4);
+        assert!(matches!(entries[0].action, Action::Genesis { .. }));
+
+        let report = verify_audit(&entries, topic.audit_head(), &topic.setting_hash);
+        assert!(report.valid, "{:?}", report.errors);
+        assert_eq!(report.setting_hash, topic.setting_hash);
+    }
+
+    #[test]
+    fn a_topic_older_than_its_log_replays_from_the_snapshot() {
+        let mut topic = topic();
+        topic.add_user("carol".into());
+        topic.add_plan_id("rice");
+        // stored and read back, the way topics from before the log are
+        let mut topic: Topic = serde_json::from_str(&serde_json::to_string(&topic).unwrap()).unwrap();
+
+        let entries = logged(&mut topic, changes());
+
+        let report = verify_audit(&entries, topic.audit_head(), &topic.setting_hash);
+        assert!(report.valid, "{:?}", report.errors);
+    }
+
+    #[test]
+    fn tampering_is_found() {
+        let mut topic = topic();
+        let entries = logged(&mut topic, changes());
+        let (head, hash) = (topic.audit_head().to_owned(), topic.setting_hash.to_owned());
+
+        let mut changed = entries.clone();
+        changed[3].action = Action::Vote { voter: "bob".into(), vote: BTreeMap::new() };
+        let report = verify_audit(&changed, &head, &hash);
+        assert!(!report.valid);
+        assert!(report.errors.iter().any(|x| x == "entry 4 does not match its hash"));
+
+        let mut missing = entries.clone();
+        missing.remove(2);
+        assert!(!verify_audit(&missing, &head, &hash).valid);
+
+        let mut restarted = entries.clone();
+        restarted[2].action = entries[0].action.to_owned();
+        let report = verify_audit(&restarted, &head, &hash);
+        assert!(report.errors.iter().any(|x| x == "entry 3 starts the log over"));
+
+        let report = verify_audit(&entries, &head, "some other setting");
+        assert!(!report.valid);
+    }
+
+    #[test]
+    fn a_log_has_to_start_with_a_snapshot() {
+        let mut topic = topic();
+        let mut entries = logged(&mut topic, changes());
+        entries.remove(0);
+
+        // a chain that is whole in itself, only without the genesis
+        let mut head = AuditHead::default();
+        let rewritten: Vec<AuditEntry> = entries
+            .into_iter()
+            .map(|x| AuditEntry::append(&mut head, &x.topic_id, x.at, x.by.as_deref(), x.action, &x.setting_hash))
+            .collect();
+
+        let report = verify_audit(&rewritten, &head, &topic.setting_hash);
+        assert_eq!(report.errors, vec!["entry 1 is not a snapshot to start from"]);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::model::{ResultView, SettingView};
+use liq::Setting;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// what changed from one setting to another, and what that did to the result
+#[derive(Debug, Serialize)]
+pub struct SettingDiff {
+    pub from: String,
+    pub to: String,
+    pub voters_added: Vec<String>,
+    pub voters_removed: Vec<String>,
+    pub plans_added: Vec<String>,
+    pub plans_removed: Vec<String>,
+    /// only voters whose vote changed
+    pub votes: BTreeMap<String, VoteChange>,
+    pub result: ResultChange,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VoteChange {
+    pub before: Option<BTreeMap<String, f64>>,
+    pub after: Option<BTreeMap<String, f64>>,
+}
+
+/// only keys whose value moved. missing keys count as 0
+#[derive(Debug, Serialize)]
+pub struct ResultChange {
+    pub votes: BTreeMap<String, ValueChange>,
+    pub influence: BTreeMap<String, ValueChange>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValueChange {
+    pub before: f64,
+    pub after: f64,
+    pub delta: f64,
+}
+
+impl SettingDiff {
+    pub fn between(from_hash: &str, from: &Setting, to_hash: &str, to: &Setting) -> Self {
+        let before = SettingView::from(from);
+        let after = SettingView::from(to);
+
+        let mut votes = BTreeMap::new();
+        for voter in keys(&before.votes, &after.votes) {
+            let (b, a) = (before.votes.get(voter), after.votes.get(voter));
+            if b != a {
+                votes.insert(
+                    voter.to_owned(),
+                    VoteChange {
+                        before: b.cloned(),
+                        after: a.cloned(),
+                    },
+                );
+            }
+        }
+
+        let result_before = ResultView::from(&from.calculate());
+        let result_after = ResultView::from(&to.calculate());
+
+        Self {
+            from: from_hash.to_string(),
+            to: to_hash.to_string(),
+            voters_added: after.voters.difference(&before.voters).cloned().collect(),
+            voters_removed: before.voters.difference(&after.voters).cloned().collect(),
+            plans_added: after.plans.difference(&before.plans).cloned().collect(),
+            plans_removed: before.plans.difference(&after.plans).cloned().collect(),
+            votes,
+            result: ResultChange {
+                votes: changes(&result_before.votes, &result_after.votes),
+                influence: changes(&result_before.influence, &result_after.influence),
+            },
+        }
+    }
+}
+
+fn keys<'a, T>(a: &'a BTreeMap<String, T>, b: &'a BTreeMap<String, T>) -> BTreeSet<&'a String> {
+    a.keys().chain(b.keys()).collect()
+}
+
+fn changes(
+    before: &BTreeMap<String, f64>,
+    after: &BTreeMap<String, f64>,
+) -> BTreeMap<String, ValueChange> {
+    keys(before, after)
+        .into_iter()
+        .filter_map(|key| {
+            let b = before.get(key).copied().unwrap_or(0.0);
+            let a = after.get(key).copied().unwrap_or(0.0);
+
+            match (a - b).abs() > f64::EPSILON {
+                true => Some((
+                    key.to_owned(),
+                    ValueChange {
+                        before: b,
+                        after: a,
+                        delta: a - b,
+                    },
+                )),
+                false => None,
+            }
+        })
+        .collect()
+}
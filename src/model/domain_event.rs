@@ -0,0 +1,135 @@
+use crate::model::{Action, AuditEntry, Method, Role, Status};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+/// something that changed, as written to the event stream. the json is
+/// flat, `type` says which of `EventData` the rest is:
+/// `{"at": 1600000000, "by": "...", "type": "plan_added", "topic_id": "...", "plan_id": "..."}`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DomainEvent {
+    pub at: i64,
+    /// who did it, none for admins and requests without a user
+    pub by: Option<String>,
+    #[serde(flatten)]
+    pub data: EventData,
+}
+
+/// new variants may be added, consumers should skip types they don't know
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventData {
+    UserAdded {
+        user_id: String,
+    },
+    UserSignedUp {
+        user_id: String,
+    },
+    UserVerified {
+        user_id: String,
+    },
+    UserDeleted {
+        user_id: String,
+    },
+    SessionRevoked {
+        user_id: String,
+        session_id: String,
+    },
+    SessionsRevoked {
+        user_id: String,
+        count: usize,
+    },
+    TopicCreated {
+        topic_id: String,
+    },
+    TopicDeleted {
+        topic_id: String,
+    },
+    PlanAdded {
+        topic_id: String,
+        plan_id: String,
+    },
+    PlanRemoved {
+        topic_id: String,
+        plan_id: String,
+    },
+    VoterAdded {
+        topic_id: String,
+        user_id: String,
+    },
+    VoterRemoved {
+        topic_id: String,
+        user_id: String,
+    },
+    VoteCast {
+        topic_id: String,
+        user_id: String,
+        setting_hash: String,
+    },
+    StatusChanged {
+        topic_id: String,
+        status: Status,
+    },
+    ScheduleChanged {
+        topic_id: String,
+        opens_at: Option<i64>,
+        closes_at: Option<i64>,
+    },
+    MethodChanged {
+        topic_id: String,
+        method: Method,
+    },
+    RoleGranted {
+        topic_id: String,
+        user_id: String,
+        role: Role,
+    },
+    RoleRevoked {
+        topic_id: String,
+        user_id: String,
+    },
+}
+
+impl EventData {
+    /// what a change committed to a topic's setting amounts to, the
+    /// genesis of a log isn't one
+    pub fn committed(entry: &AuditEntry) -> Option<Self> {
+        let topic_id = entry.topic_id.to_owned();
+
+        let data = match &entry.action {
+            Action::Genesis { .. } => return None,
+            Action::Vote { voter, .. } => EventData::VoteCast {
+                topic_id,
+                user_id: voter.to_owned(),
+                setting_hash: entry.setting_hash.to_owned(),
+            },
+            Action::AddPlan { plan_id } => EventData::PlanAdded {
+                topic_id,
+                plan_id: plan_id.to_owned(),
+            },
+            Action::RemovePlan { plan_id } => EventData::PlanRemoved {
+                topic_id,
+                plan_id: plan_id.to_owned(),
+            },
+            Action::AddVoter { voter } => EventData::VoterAdded {
+                topic_id,
+                user_id: voter.to_owned(),
+            },
+            Action::RemoveVoter { voter } => EventData::VoterRemoved {
+                topic_id,
+                user_id: voter.to_owned(),
+            },
+        };
+
+        Some(data)
+    }
+}
+
+impl DomainEvent {
+    pub fn now(by: Option<&str>, data: EventData) -> Self {
+        Self {
+            at: Utc::now().timestamp(),
+            by: by.map(str::to_string),
+            data,
+        }
+    }
+}
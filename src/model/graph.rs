@@ -0,0 +1,104 @@
+use crate::model::{ResultView, SettingView};
+use serde::Serialize;
+
+/// the votes of a setting as a weighted directed graph. an edge goes from
+/// a voter to each plan or voter they gave weight to.
+#[derive(Debug, Serialize)]
+pub struct DelegationGraph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeKind {
+    Voter,
+    Plan,
+}
+
+/// `value` is a voter's influence or a plan's share of the result
+#[derive(Debug, Serialize)]
+pub struct Node {
+    pub id: String,
+    pub kind: NodeKind,
+    pub value: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub weight: f64,
+}
+
+impl DelegationGraph {
+    pub fn new(setting: &SettingView, result: &ResultView) -> Self {
+        let voters = setting.voters.iter().map(|id| Node {
+            id: id.to_owned(),
+            kind: NodeKind::Voter,
+            value: result.influence.get(id).copied().unwrap_or(0.0),
+        });
+
+        let plans = setting.plans.iter().map(|id| Node {
+            id: id.to_owned(),
+            kind: NodeKind::Plan,
+            value: result.votes.get(id).copied().unwrap_or(0.0),
+        });
+
+        let edges = setting
+            .votes
+            .iter()
+            .flat_map(|(from, vote)| {
+                vote.iter().map(move |(to, weight)| Edge {
+                    from: from.to_owned(),
+                    to: to.to_owned(),
+                    weight: *weight,
+                })
+            })
+            .collect();
+
+        Self {
+            nodes: voters.chain(plans).collect(),
+            edges,
+        }
+    }
+
+    /// GraphViz source, voters as ellipses and plans as boxes
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph delegation {\n");
+
+        for node in self.nodes.iter() {
+            let shape = match node.kind {
+                NodeKind::Voter => "ellipse",
+                NodeKind::Plan => "box",
+            };
+            dot.push_str(&format!(
+                "    {} [shape={}, label={}];\n",
+                quote(&node.id),
+                shape,
+                quote(&format!("{}\n{:.3}", node.id, node.value)),
+            ));
+        }
+
+        for edge in self.edges.iter() {
+            dot.push_str(&format!(
+                "    {} -> {} [penwidth={:.2}, label=\"{:.3}\"];\n",
+                quote(&edge.from),
+                quote(&edge.to),
+                1.0 + 4.0 * edge.weight,
+                edge.weight,
+            ));
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+// ids are user input as far as DOT is concerned
+fn quote(x: &str) -> String {
+    format!(
+        "\"{}\"",
+        x.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+    )
+}
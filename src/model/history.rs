@@ -0,0 +1,45 @@
+use crate::model::Settable;
+use serde::{Deserialize, Serialize};
+
+/// what happened to the setting of a topic
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Change {
+    Vote { voter: String },
+    AddPlan { plan_id: String },
+    RemovePlan { plan_id: String },
+    AddVoter { voter: String },
+    RemoveVoter { voter: String },
+}
+
+/// one step in the history of a topic, stored under
+/// `history:{topic_id}:{seq}`. `hash` is the setting it led to, stored
+/// under `setting:{hash}`, `prev_hash` the one before.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    // not there in entries kept inline on older topics
+    #[serde(default)]
+    pub topic_id: String,
+    #[serde(default)]
+    pub seq: u64,
+    pub hash: String,
+    pub prev_hash: String,
+    pub at: i64,
+    // none for admins
+    pub by: Option<String>,
+    pub change: Change,
+}
+
+impl Settable for HistoryEntry {
+    fn domain_prefix() -> String {
+        String::from("history")
+    }
+
+    fn id(&self) -> String {
+        format!("{}:{}", self.topic_id, self.seq)
+    }
+
+    fn list_item(&self) -> String {
+        self.id()
+    }
+}
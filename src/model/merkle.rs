@@ -0,0 +1,202 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+type Ballot = BTreeMap<String, f64>;
+type Hash = [u8; 32];
+
+// leaves and inner nodes are hashed with different prefixes, so a node
+// can never pass for a ballot
+const LEAF: u8 = 0;
+const NODE: u8 = 1;
+
+/// a merkle tree over every voter's ballot, ordered by voter id.
+/// a leaf is sha256(0x00 || json([voter, ballot])), a node
+/// sha256(0x01 || left || right). an odd node out is carried up as is.
+#[derive(Debug)]
+pub struct BallotTree {
+    voters: Vec<(String, Ballot)>,
+    levels: Vec<Vec<Hash>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProofStep {
+    pub hash: String,
+    /// which side the sibling is on, "left" or "right"
+    pub side: &'static str,
+}
+
+/// everything a voter needs to check their ballot is under `root`,
+/// without learning anyone else's
+#[derive(Debug, Serialize)]
+pub struct InclusionProof {
+    pub voter: String,
+    pub ballot: Ballot,
+    pub index: usize,
+    pub leaf: String,
+    pub path: Vec<ProofStep>,
+    pub root: String,
+}
+
+fn leaf_hash(voter: &str, ballot: &Ballot) -> Hash {
+    let json = serde_json::to_vec(&(voter, ballot)).expect("a ballot should be Serializable");
+
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF]);
+    hasher.update(&json);
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+fn hex(hash: &Hash) -> String {
+    hash.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+impl BallotTree {
+    pub fn new(votes: &BTreeMap<String, Ballot>) -> Self {
+        let voters: Vec<(String, Ballot)> = votes
+            .iter()
+            .map(|(voter, ballot)| (voter.to_owned(), ballot.to_owned()))
+            .collect();
+
+        let mut levels = vec![voters
+            .iter()
+            .map(|(voter, ballot)| leaf_hash(voter, ballot))
+            .collect::<Vec<Hash>>()];
+
+        while levels.last().map_or(0, Vec::len) > 1 {
+            let below = levels.last().expect("checked above");
+            let level = below
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node_hash(left, right),
+                    [single] => *single,
+                    _ => unreachable!("chunks of two"),
+                })
+                .collect();
+            levels.push(level);
+        }
+
+        Self { voters, levels }
+    }
+
+    /// `None` when nobody voted
+    pub fn root(&self) -> Option<String> {
+        self.levels.last()?.first().map(hex)
+    }
+
+    pub fn proof(&self, voter: &str) -> Option<InclusionProof> {
+        let index = self.voters.iter().position(|(x, _)| x == voter)?;
+        let (_, ballot) = &self.voters[index];
+
+        let mut path = Vec::new();
+        let mut i = index;
+
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = i ^ 1;
+            // the odd one out has no sibling and moves up unchanged
+            if let Some(hash) = level.get(sibling) {
+                path.push(ProofStep {
+                    hash: hex(hash),
+                    side: if sibling < i { "left" } else { "right" },
+                });
+            }
+            i /= 2;
+        }
+
+        Some(InclusionProof {
+            voter: voter.to_string(),
+            ballot: ballot.to_owned(),
+            index,
+            leaf: hex(&self.levels[0][index]),
+            path,
+            root: self.root()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(x: &str) -> Hash {
+        let mut hash = [0; 32];
+        for (i, byte) in hash.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&x[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        hash
+    }
+
+    // what a voter would do with their proof
+    fn check(proof: &InclusionProof) -> bool {
+        let leaf = leaf_hash(&proof.voter, &proof.ballot);
+        if hex(&leaf) != proof.leaf {
+            return false;
+        }
+
+        let top = proof.path.iter().fold(leaf, |acc, step| match step.side {
+            "left" => node_hash(&unhex(&step.hash), &acc),
+            _ => node_hash(&acc, &unhex(&step.hash)),
+        });
+
+        hex(&top) == proof.root
+    }
+
+    fn votes(n: usize) -> BTreeMap<String, Ballot> {
+        (0..n)
+            .map(|i| {
+                let mut ballot = Ballot::new();
+                ballot.insert("plan".to_string(), i as f64);
+                (format!("voter{}", i), ballot)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nobody_voted() {
+        let tree = BallotTree::new(&BTreeMap::new());
+        assert_eq!(tree.root(), None);
+        assert!(tree.proof("anyone").is_none());
+    }
+
+    #[test]
+    fn every_voter_can_prove_their_ballot() {
+        for n in 1..=9 {
+            let tree = BallotTree::new(&votes(n));
+
+            for voter in votes(n).keys() {
+                let proof = tree.proof(voter).unwrap();
+                assert!(check(&proof), "{} of {} voters", voter, n);
+            }
+        }
+    }
+
+    #[test]
+    fn a_changed_ballot_does_not_prove() {
+        let tree = BallotTree::new(&votes(5));
+        let mut proof = tree.proof("voter3").unwrap();
+
+        proof.ballot.insert("plan".to_string(), 100.0);
+        proof.leaf = hex(&leaf_hash(&proof.voter, &proof.ballot));
+
+        assert!(!check(&proof));
+    }
+
+    #[test]
+    fn the_root_covers_every_ballot() {
+        let before = BallotTree::new(&votes(4)).root();
+
+        let mut changed = votes(4);
+        changed.get_mut("voter0").unwrap().insert("plan".to_string(), 0.5);
+
+        assert_ne!(BallotTree::new(&changed).root(), before);
+        assert_eq!(BallotTree::new(&votes(4)).root(), before);
+    }
+}
@@ -1,14 +1,34 @@
 mod user;
 mod topic;
 mod plan;
+mod setting;
+mod history;
+mod diff;
+mod audit;
+mod merkle;
+mod vote;
+mod graph;
+mod tally;
+mod webhook;
+mod domain_event;
 
 use liq::Setting;
 use serde::Serialize;
 use std::fmt::Debug;
 
 pub use user::{User, PartialUser};
-pub use topic::{Topic, PartialTopic};
-pub use plan::{Plan, RawPlan};
+pub use topic::{Topic, PartialTopic, Role, Schedule, Status};
+pub use plan::{FieldError, Plan, PlanError, RawPlan};
+pub use setting::{ResultView, SettingLimits, SettingView};
+pub use history::{Change, HistoryEntry};
+pub use diff::SettingDiff;
+pub use audit::{verify_audit, Action, AuditEntry, AuditHead};
+pub use merkle::BallotTree;
+pub use vote::{Normalization, Vote, VoteError};
+pub use graph::DelegationGraph;
+pub use tally::{Method, TallyResult};
+pub use webhook::{Delivery, Event, EventKind, Webhook};
+pub use domain_event::{DomainEvent, EventData};
 
 pub trait Settable: Serialize + Debug {
     fn domain_prefix() -> String;
@@ -21,7 +41,7 @@ pub trait Settable: Serialize + Debug {
     fn list_item(&self) -> String;
 
     fn domain(&self) -> String {
-        return format!("{}:{}", Self::domain_prefix(), &self.id());
+        format!("{}:{}", Self::domain_prefix(), &self.id())
     }
 
     fn json(&self) -> String {
@@ -1,7 +1,8 @@
 use crate::model::Settable;
 use bs58::encode;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
 use std::fmt::Debug;
 
 // a plan is one option within a topic that could be voted to.
@@ -58,49 +59,206 @@ pub struct RawPlan {
     data: serde_json::Value,
 }
 
-impl From<RawPlan> for Plan {
-    fn from(raw: RawPlan) -> Self {
-        match raw.kind.to_lowercase().as_str() {
-            "simple" => {
-                let title: String =
-                    serde_json::from_str(&raw.data.to_string()).expect("should be just a string");
-                Plan::Simple(title)
+const MAX_TITLE_LEN: usize = 200;
+const MAX_DESCRIPTION_LEN: usize = 5000;
+const MAX_URL_LEN: usize = 2048;
+
+/// one problem with one field of a plan, `field` is a path into the
+/// request body like `data[1].radius`. `data` is an array for most kinds,
+/// so it's indexed like one
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub struct PlanError(pub Vec<FieldError>);
+
+impl PlanError {
+    fn single(field: &str, message: String) -> Self {
+        PlanError(vec![FieldError {
+            field: field.to_string(),
+            message,
+        }])
+    }
+}
+
+impl std::fmt::Display for PlanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fields: Vec<String> = self
+            .0
+            .iter()
+            .map(|e| format!("{}: {}", e.field, e.message))
+            .collect();
+        write!(f, "invalid plan ({})", fields.join("; "))
+    }
+}
+
+fn data<T: DeserializeOwned>(raw: &RawPlan, shape: &str) -> Result<T, PlanError> {
+    serde_json::from_value(raw.data.to_owned()).map_err(|_| {
+        PlanError::single(
+            "data",
+            format!(
+                "data of a '{}' plan should be {}",
+                raw.kind.to_lowercase(),
+                shape
+            ),
+        )
+    })
+}
+
+// collects problems instead of stopping at the first one,
+// so clients can mark every bad field at once
+#[derive(Default)]
+struct Checks(Vec<FieldError>);
+
+impl Checks {
+    fn fail(&mut self, field: &str, message: String) {
+        self.0.push(FieldError {
+            field: field.to_string(),
+            message,
+        });
+    }
+
+    fn text(&mut self, field: &str, value: &str, max_len: usize) {
+        if value.trim().is_empty() {
+            self.fail(field, "should not be empty".into());
+        } else if value.chars().count() > max_len {
+            self.fail(field, format!("should be at most {} characters", max_len));
+        }
+    }
+
+    fn name(&mut self, field: &str, value: &Option<String>) {
+        if let Some(x) = value {
+            self.text(field, x, MAX_TITLE_LEN);
+        }
+    }
+
+    fn url(&mut self, field: &str, value: &str) {
+        if value.len() > MAX_URL_LEN {
+            self.fail(field, format!("should be at most {} characters", MAX_URL_LEN));
+            return;
+        }
+
+        match url::Url::parse(value) {
+            Ok(x) if x.scheme() == "http" || x.scheme() == "https" => {}
+            Ok(_) => self.fail(field, "should be an http or https url".into()),
+            Err(e) => self.fail(field, format!("is not a valid url ({})", e)),
+        }
+    }
+
+    fn lat_lng(&mut self, field: &str, lat: f64, lng: f64) {
+        if !(lat.is_finite() && (-90.0..=90.0).contains(&lat)) {
+            self.fail(&format!("{}.lat", field), "should be between -90 and 90".into());
+        }
+        if !(lng.is_finite() && (-180.0..=180.0).contains(&lng)) {
+            self.fail(&format!("{}.lng", field), "should be between -180 and 180".into());
+        }
+    }
+}
+
+impl Plan {
+    fn validate(&self) -> Result<(), PlanError> {
+        let mut checks = Checks::default();
+
+        match self {
+            Plan::Simple(title) => checks.text("data", title, MAX_TITLE_LEN),
+            Plan::Long(title, description) => {
+                checks.text("data[0]", title, MAX_TITLE_LEN);
+                checks.text("data[1]", description, MAX_DESCRIPTION_LEN);
+            }
+            Plan::Url(title, url) => {
+                checks.text("data[0]", title, MAX_TITLE_LEN);
+                checks.url("data[1]", url);
+            }
+            Plan::Image(url) => checks.url("data", url),
+            Plan::LatLng(name, location) => {
+                checks.name("data[0]", name);
+                checks.lat_lng("data[1]", location.lat, location.lng);
+            }
+            Plan::Circle(name, area) => {
+                checks.name("data[0]", name);
+                checks.lat_lng("data[1]", area.lat, area.lng);
+                if !(area.radius.is_finite() && area.radius > 0.0) {
+                    checks.fail("data[1].radius", "should be a positive number".into());
+                }
             }
+            Plan::Path(name, indices) => {
+                checks.name("data[0]", name);
+                if indices.is_empty() {
+                    checks.fail("data[1]", "should have at least one point".into());
+                }
+                if let Some(i) = indices.iter().position(|(x, y)| !(x.is_finite() && y.is_finite())) {
+                    checks.fail(&format!("data[1][{}]", i), "should be finite numbers".into());
+                }
+            }
+        }
+
+        if checks.0.is_empty() {
+            Ok(())
+        } else {
+            Err(PlanError(checks.0))
+        }
+    }
+}
+
+impl TryFrom<RawPlan> for Plan {
+    type Error = PlanError;
+
+    fn try_from(raw: RawPlan) -> Result<Self, Self::Error> {
+        let plan = match raw.kind.to_lowercase().as_str() {
+            "simple" => Plan::Simple(data(&raw, "a string")?),
             "long" => {
-                let (title, description): (String, String) =
-                    serde_json::from_str(&raw.data.to_string())
-                        .expect("this should be deserializable");
+                let (title, description) = data(&raw, "[title, description]")?;
                 Plan::Long(title, description)
             }
             "url" => {
-                let (title, url): (String, String) = serde_json::from_str(&raw.data.to_string())
-                    .expect("this should be deserializable");
+                let (title, url) = data(&raw, "[title, url]")?;
                 Plan::Url(title, url)
             }
-            "image" => {
-                let url: String =
-                    serde_json::from_str(&raw.data.to_string()).expect("should be just a string");
-                Plan::Image(url)
-            }
+            "image" => Plan::Image(data(&raw, "a url string")?),
             "latlng" => {
-                let (name, location): (Option<String>, Location) =
-                    serde_json::from_str(&raw.data.to_string())
-                        .expect("this should be Deserialize");
+                let (name, location) = data(&raw, "[name or null, {lat, lng}]")?;
                 Plan::LatLng(name, location)
             }
             "circle" => {
-                let (name, area): (Option<String>, CircularArea) =
-                    serde_json::from_str(&raw.data.to_string())
-                        .expect("this should be Deserialize");
+                let (name, area) = data(&raw, "[name or null, {lat, lng, radius}]")?;
                 Plan::Circle(name, area)
             }
             "path" => {
-                let (name, indices): (Option<String>, Vec<(f64, f64)>) =
-                    serde_json::from_str(&raw.data.to_string())
-                        .expect("this should be Deserialize");
+                let (name, indices) = data(&raw, "[name or null, [[x, y], ...]]")?;
                 Plan::Path(name, indices)
             }
-            _ => Plan::Simple("unknown type".into()),
+            x => {
+                return Err(PlanError::single(
+                    "type",
+                    format!(
+                        "unknown plan type '{}', expected one of simple, long, url, image, latlng, circle, path",
+                        x
+                    ),
+                ))
+            }
+        };
+
+        plan.validate()?;
+
+        Ok(plan)
+    }
+}
+
+impl Plan {
+    /// what to call the plan when listing or charting it
+    pub fn label(&self) -> String {
+        match &self {
+            Plan::Simple(x) | Plan::Image(x) => x.to_string(),
+            Plan::Long(x, _y) | Plan::Url(x, _y) => x.to_string(),
+            Plan::LatLng(_name, location) => location.to_string(),
+            Plan::Circle(_name, area) => area.to_string(),
+            Plan::Path(name, _indices) => match name {
+                Some(v) => v.to_string(),
+                None => "".to_string(),
+            },
         }
     }
 }
@@ -135,32 +293,7 @@ impl Settable for Plan {
     }
 
     fn list_item(&self) -> String {
-        let entry = match &self {
-            Plan::Simple(x) | Plan::Image(x) => {
-                let x = x.to_string();
-                vec![self.id(), x]
-            }
-            Plan::Long(x, _y) | Plan::Url(x, _y) => {
-                let id = self.id();
-                vec![id, x.to_string()]
-            }
-            Plan::LatLng(_name, location) => {
-                let id = self.id();
-                vec![id, location.to_string()]
-            }
-            Plan::Circle(_name, area) => {
-                let id = self.id();
-                vec![id, area.to_string()]
-            }
-            Plan::Path(name, _indices) => {
-                let id = self.id();
-                let info = match name {
-                    Some(v) => v,
-                    None => ""
-                };
-                vec![id, info.to_string()]
-            }
-        };
+        let entry = vec![self.id(), self.label()];
 
         serde_json::to_string(&entry).expect("should be Serializable")
     }
@@ -170,10 +303,75 @@ impl Settable for Plan {
     }
 
     fn domain(&self) -> String {
-        return format!("{}:{}", Self::domain_prefix(), &self.id());
+        format!("{}:{}", Self::domain_prefix(), &self.id())
     }
 
     fn json(&self) -> String {
         serde_json::to_string(&self).expect("I should be Serialize-able")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::convert::TryInto;
+
+    fn plan(kind: &str, data: serde_json::Value) -> Result<Plan, PlanError> {
+        serde_json::from_value::<RawPlan>(json!({"type": kind, "data": data}))
+            .unwrap()
+            .try_into()
+    }
+
+    fn fields(result: Result<Plan, PlanError>) -> Vec<String> {
+        match result {
+            Ok(x) => panic!("expected field errors, got {:?}", x),
+            Err(e) => e.0.into_iter().map(|x| x.field).collect(),
+        }
+    }
+
+    #[test]
+    fn accepts_every_kind() {
+        assert!(plan("simple", json!("bread")).is_ok());
+        assert!(plan("Long", json!(["bread", "with butter"])).is_ok());
+        assert!(plan("url", json!(["docs", "https://example.com"])).is_ok());
+        assert!(plan("image", json!("http://example.com/a.png")).is_ok());
+        assert!(plan("latlng", json!([null, {"lat": 35.6, "lng": 139.7}])).is_ok());
+        assert!(plan("circle", json!(["park", {"lat": 0, "lng": 0, "radius": 10}])).is_ok());
+        assert!(plan("path", json!([null, [[0, 0], [1, 1]]])).is_ok());
+    }
+
+    #[test]
+    fn unknown_type_and_wrong_shape() {
+        assert_eq!(fields(plan("poll", json!("x"))), vec!["type"]);
+        assert_eq!(fields(plan("long", json!("only a title"))), vec!["data"]);
+    }
+
+    #[test]
+    fn every_bad_field_is_listed_by_index() {
+        let long = plan("long", json!(["  ", "x".repeat(MAX_DESCRIPTION_LEN + 1)]));
+        assert_eq!(fields(long), vec!["data[0]", "data[1]"]);
+
+        let url = plan("url", json!(["docs", "ftp://example.com"]));
+        assert_eq!(fields(url), vec!["data[1]"]);
+
+        let place = plan("latlng", json!(["", {"lat": 91, "lng": -181}]));
+        assert_eq!(fields(place), vec!["data[0]", "data[1].lat", "data[1].lng"]);
+
+        let circle = plan("circle", json!([null, {"lat": 0, "lng": 0, "radius": 0}]));
+        assert_eq!(fields(circle), vec!["data[1].radius"]);
+
+        assert_eq!(fields(plan("path", json!([null, []]))), vec!["data[1]"]);
+        assert_eq!(fields(plan("simple", json!("x".repeat(MAX_TITLE_LEN + 1)))), vec!["data"]);
+    }
+
+    #[test]
+    fn same_content_same_id() {
+        let a = plan("simple", json!("bread")).unwrap();
+        let b = plan("simple", json!("bread")).unwrap();
+        let c = plan("simple", json!("rice")).unwrap();
+
+        assert_eq!(a.id(), b.id());
+        assert_ne!(a.id(), c.id());
+    }
+}
@@ -0,0 +1,117 @@
+use dotenv::dotenv;
+use liq::{PollResult, Setting};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// a read-only look into liq's `Setting`, built from its serialized form.
+/// liq does not hand out its voters, plans and votes directly, so anything
+/// that needs to walk them goes through here.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct SettingView {
+    pub voters: BTreeSet<String>,
+    pub plans: BTreeSet<String>,
+    pub votes: BTreeMap<String, BTreeMap<String, f64>>,
+}
+
+impl From<&Setting> for SettingView {
+    fn from(setting: &Setting) -> Self {
+        serde_json::to_value(setting)
+            .and_then(serde_json::from_value)
+            .expect("Setting should serialize with voters, plans and votes")
+    }
+}
+
+impl SettingView {
+    /// every weight of every ballot
+    pub fn ballot_entries(&self) -> usize {
+        self.votes.values().map(|x| x.len()).sum()
+    }
+}
+
+/// how big a setting anyone may have calculated is. liq's work grows with
+/// all three, topics are bounded by who runs them but raw settings are not.
+/// set with `RAW_MAX_VOTERS`, `RAW_MAX_PLANS` and `RAW_MAX_BALLOT_ENTRIES`.
+#[derive(Debug, Clone, Copy)]
+pub struct SettingLimits {
+    pub voters: usize,
+    pub plans: usize,
+    pub ballot_entries: usize,
+}
+
+fn env_limit(key: &str, default: usize) -> usize {
+    std::env::var(key)
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(default)
+}
+
+impl SettingLimits {
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        Self {
+            voters: env_limit("RAW_MAX_VOTERS", 1000),
+            plans: env_limit("RAW_MAX_PLANS", 100),
+            ballot_entries: env_limit("RAW_MAX_BALLOT_ENTRIES", 10_000),
+        }
+    }
+
+    /// says which limit the setting goes over, if any
+    pub fn check(&self, setting: &SettingView) -> Result<(), String> {
+        let counts = [
+            ("voters", setting.voters.len(), self.voters),
+            ("plans", setting.plans.len(), self.plans),
+            ("ballot entries", setting.ballot_entries(), self.ballot_entries),
+        ];
+
+        match counts.iter().find(|(_, count, max)| count > max) {
+            Some((what, count, max)) => Err(format!("{} {}, at most {} allowed", count, what, max)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// same for `PollResult`: the voting weight each plan ended up with and
+/// the influence each voter had
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ResultView {
+    pub votes: BTreeMap<String, f64>,
+    pub influence: BTreeMap<String, f64>,
+}
+
+impl From<&PollResult> for ResultView {
+    fn from(result: &PollResult) -> Self {
+        serde_json::to_value(result)
+            .and_then(serde_json::from_value)
+            .expect("PollResult should serialize with votes and influence")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_name_what_is_too_much() {
+        let view: SettingView = serde_json::from_value(serde_json::json!({
+            "voters": ["a", "b"],
+            "plans": ["p"],
+            "votes": { "a": { "p": 1.0, "b": 0.0 } },
+        }))
+        .unwrap();
+        let limits = SettingLimits {
+            voters: 2,
+            plans: 1,
+            ballot_entries: 2,
+        };
+
+        assert_eq!(limits.check(&view), Ok(()));
+        assert_eq!(
+            SettingLimits { voters: 1, ..limits }.check(&view),
+            Err("2 voters, at most 1 allowed".to_string())
+        );
+        assert_eq!(
+            SettingLimits { ballot_entries: 1, ..limits }.check(&view),
+            Err("2 ballot entries, at most 1 allowed".to_string())
+        );
+    }
+}
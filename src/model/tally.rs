@@ -0,0 +1,353 @@
+use crate::model::{ResultView, SettingView};
+use liq::Setting;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// how the ballots of a topic are counted. everything but `liquid` only
+/// looks at the weights voters gave to plans directly, delegations are
+/// ignored. a ballot's ranking is its plans by weight, highest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Method {
+    /// liq's own calculation, delegations included
+    #[default]
+    Liquid,
+    /// one vote for each voter's top plan
+    Plurality,
+    /// one vote for every plan a voter gave any weight
+    Approval,
+    /// with n plans, a voter's first gets n - 1 points, the second n - 2 ...
+    Borda,
+    /// top plans are counted, the last one is dropped until one has a majority
+    InstantRunoff,
+    /// weights are credits, a plan gets the square root of what was spent on it
+    Quadratic,
+}
+
+impl Method {
+    pub const ALL: [Method; 6] = [
+        Method::Liquid,
+        Method::Plurality,
+        Method::Approval,
+        Method::Borda,
+        Method::InstantRunoff,
+        Method::Quadratic,
+    ];
+
+    pub fn tally(&self) -> &'static dyn Tally {
+        match self {
+            Method::Liquid => &Liquid,
+            Method::Plurality => &Plurality,
+            Method::Approval => &Approval,
+            Method::Borda => &Borda,
+            Method::InstantRunoff => &InstantRunoff,
+            Method::Quadratic => &Quadratic,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TallyResult {
+    pub method: Method,
+    /// points per plan
+    pub votes: BTreeMap<String, f64>,
+    /// only `liquid` has influence
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub influence: Option<BTreeMap<String, f64>>,
+    /// only `instant_runoff` has rounds, the counts of each
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rounds: Vec<BTreeMap<String, f64>>,
+}
+
+impl TallyResult {
+    fn votes(method: Method, votes: BTreeMap<String, f64>) -> Self {
+        Self {
+            method,
+            votes,
+            influence: None,
+            rounds: Vec::new(),
+        }
+    }
+}
+
+pub trait Tally {
+    fn tally(&self, setting: &Setting) -> TallyResult;
+}
+
+/// the plans of each ballot by weight, highest first. zero and negative
+/// weights and keys that aren't plans are left out
+fn rankings(view: &SettingView) -> Vec<Vec<(String, f64)>> {
+    view.votes
+        .values()
+        .map(|vote| {
+            let mut ranking: Vec<(String, f64)> = vote
+                .iter()
+                .filter(|(key, weight)| view.plans.contains(*key) && **weight > 0.0)
+                .map(|(key, weight)| (key.to_owned(), *weight))
+                .collect();
+            // ties go to the smaller id, so counting is deterministic
+            ranking.sort_by(|a, b| {
+                b.1.partial_cmp(&a.1)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .then_with(|| a.0.cmp(&b.0))
+            });
+            ranking
+        })
+        .collect()
+}
+
+// every plan shows up, also the ones nobody voted for
+fn zeros(view: &SettingView) -> BTreeMap<String, f64> {
+    view.plans.iter().map(|x| (x.to_owned(), 0.0)).collect()
+}
+
+pub struct Liquid;
+
+impl Tally for Liquid {
+    fn tally(&self, setting: &Setting) -> TallyResult {
+        let result = ResultView::from(&setting.calculate());
+
+        TallyResult {
+            influence: Some(result.influence),
+            ..TallyResult::votes(Method::Liquid, result.votes)
+        }
+    }
+}
+
+pub struct Plurality;
+
+impl Tally for Plurality {
+    fn tally(&self, setting: &Setting) -> TallyResult {
+        let view = SettingView::from(setting);
+        let mut votes = zeros(&view);
+
+        for ranking in rankings(&view) {
+            if let Some((top, _)) = ranking.first() {
+                *votes.entry(top.to_owned()).or_default() += 1.0;
+            }
+        }
+
+        TallyResult::votes(Method::Plurality, votes)
+    }
+}
+
+pub struct Approval;
+
+impl Tally for Approval {
+    fn tally(&self, setting: &Setting) -> TallyResult {
+        let view = SettingView::from(setting);
+        let mut votes = zeros(&view);
+
+        for ranking in rankings(&view) {
+            for (plan, _) in ranking {
+                *votes.entry(plan).or_default() += 1.0;
+            }
+        }
+
+        TallyResult::votes(Method::Approval, votes)
+    }
+}
+
+pub struct Borda;
+
+impl Tally for Borda {
+    fn tally(&self, setting: &Setting) -> TallyResult {
+        let view = SettingView::from(setting);
+        let mut votes = zeros(&view);
+        let n = view.plans.len();
+
+        for ranking in rankings(&view) {
+            for (rank, (plan, _)) in ranking.into_iter().enumerate() {
+                *votes.entry(plan).or_default() += (n - 1 - rank) as f64;
+            }
+        }
+
+        TallyResult::votes(Method::Borda, votes)
+    }
+}
+
+pub struct InstantRunoff;
+
+impl Tally for InstantRunoff {
+    fn tally(&self, setting: &Setting) -> TallyResult {
+        let view = SettingView::from(setting);
+        let rankings = rankings(&view);
+        let mut remaining: BTreeSet<String> = view.plans.clone();
+        let mut rounds: Vec<BTreeMap<String, f64>> = Vec::new();
+
+        loop {
+            let mut counts: BTreeMap<String, f64> =
+                remaining.iter().map(|x| (x.to_owned(), 0.0)).collect();
+
+            for ranking in rankings.iter() {
+                // exhausted ballots count for nobody
+                if let Some((plan, _)) = ranking.iter().find(|(x, _)| remaining.contains(x)) {
+                    *counts.entry(plan.to_owned()).or_default() += 1.0;
+                }
+            }
+
+            let total: f64 = counts.values().sum();
+            let majority = counts.values().any(|x| *x * 2.0 > total);
+
+            // on a tie for last, the larger id goes
+            let last = counts
+                .iter()
+                .min_by(|a, b| {
+                    a.1.partial_cmp(b.1)
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                        .then_with(|| b.0.cmp(a.0))
+                })
+                .map(|(x, _)| x.to_owned());
+
+            rounds.push(counts);
+
+            match last {
+                Some(last) if !majority && total > 0.0 && remaining.len() > 1 => {
+                    remaining.remove(&last);
+                }
+                _ => break,
+            }
+        }
+
+        // plans dropped along the way end up with 0
+        let mut votes = zeros(&view);
+        if let Some(last_round) = rounds.last() {
+            votes.extend(last_round.iter().map(|(x, count)| (x.to_owned(), *count)));
+        }
+
+        TallyResult {
+            rounds,
+            ..TallyResult::votes(Method::InstantRunoff, votes)
+        }
+    }
+}
+
+pub struct Quadratic;
+
+impl Tally for Quadratic {
+    fn tally(&self, setting: &Setting) -> TallyResult {
+        let view = SettingView::from(setting);
+        let mut votes = zeros(&view);
+
+        for ranking in rankings(&view) {
+            for (plan, credits) in ranking {
+                *votes.entry(plan).or_default() += credits.sqrt();
+            }
+        }
+
+        TallyResult::votes(Method::Quadratic, votes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ballot(weights: &[(&str, f64)]) -> BTreeMap<String, f64> {
+        weights.iter().map(|(k, w)| (k.to_string(), *w)).collect()
+    }
+
+    // v5 also delegates, v6 gives nothing, neither counts for a plan
+    fn setting() -> Setting {
+        let mut setting = Setting::new();
+        for plan in ["a", "b", "c"].iter() {
+            setting.add_plan(plan);
+        }
+
+        let ballots = [
+            ("v1", ballot(&[("a", 0.6), ("b", 0.4)])),
+            ("v2", ballot(&[("a", 1.0)])),
+            ("v3", ballot(&[("b", 0.5), ("c", 0.5)])),
+            ("v4", ballot(&[("c", 0.7), ("b", 0.3)])),
+            ("v5", ballot(&[("c", 0.5), ("v1", 0.5)])),
+            ("v6", ballot(&[("a", 0.0)])),
+        ];
+        for (voter, vote) in ballots.iter() {
+            setting.add_voter(voter);
+            setting.overwrite_vote(voter, vote.to_owned());
+        }
+
+        setting
+    }
+
+    fn votes(method: Method) -> BTreeMap<String, f64> {
+        method.tally().tally(&setting()).votes
+    }
+
+    #[test]
+    fn rankings_go_by_weight_then_id() {
+        let view = SettingView::from(&setting());
+        let tops: Vec<Vec<String>> = rankings(&view)
+            .into_iter()
+            .map(|x| x.into_iter().map(|(plan, _)| plan).collect())
+            .collect();
+
+        assert_eq!(tops, vec![
+            vec!["a", "b"],
+            vec!["a"],
+            vec!["b", "c"],
+            vec!["c", "b"],
+            vec!["c"],
+            vec![],
+        ]);
+    }
+
+    #[test]
+    fn plurality() {
+        assert_eq!(votes(Method::Plurality), ballot(&[("a", 2.0), ("b", 1.0), ("c", 2.0)]));
+    }
+
+    #[test]
+    fn approval() {
+        assert_eq!(votes(Method::Approval), ballot(&[("a", 2.0), ("b", 3.0), ("c", 3.0)]));
+    }
+
+    #[test]
+    fn borda() {
+        assert_eq!(votes(Method::Borda), ballot(&[("a", 4.0), ("b", 4.0), ("c", 5.0)]));
+    }
+
+    #[test]
+    fn instant_runoff_drops_the_last_until_a_majority() {
+        let result = Method::InstantRunoff.tally().tally(&setting());
+
+        assert_eq!(result.rounds, vec![
+            ballot(&[("a", 2.0), ("b", 1.0), ("c", 2.0)]),
+            ballot(&[("a", 2.0), ("c", 3.0)]),
+        ]);
+        assert_eq!(result.votes, ballot(&[("a", 2.0), ("b", 0.0), ("c", 3.0)]));
+    }
+
+    #[test]
+    fn instant_runoff_without_ballots() {
+        let mut setting = Setting::new();
+        setting.add_plan("a");
+        setting.add_plan("b");
+
+        let result = Method::InstantRunoff.tally().tally(&setting);
+        assert_eq!(result.rounds.len(), 1);
+        assert_eq!(result.votes, ballot(&[("a", 0.0), ("b", 0.0)]));
+    }
+
+    #[test]
+    fn quadratic() {
+        let expected = ballot(&[
+            ("a", 0.6f64.sqrt() + 1.0),
+            ("b", 0.4f64.sqrt() + 0.5f64.sqrt() + 0.3f64.sqrt()),
+            ("c", 0.5f64.sqrt() + 0.7f64.sqrt() + 0.5f64.sqrt()),
+        ]);
+
+        for (plan, x) in votes(Method::Quadratic) {
+            assert!((x - expected[&plan]).abs() < 1e-9, "{}: {}", plan, x);
+        }
+    }
+
+    #[test]
+    fn only_liquid_has_influence() {
+        for method in Method::ALL.iter() {
+            let result = method.tally().tally(&setting());
+            assert_eq!(result.method, *method);
+            assert_eq!(result.influence.is_some(), *method == Method::Liquid);
+        }
+    }
+}
@@ -1,20 +1,125 @@
 use bs58::encode;
+use chrono::Utc;
 use liq::{PollResult, Setting};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::collections::BTreeMap;
 use std::fmt::Debug;
-use crate::model::Settable;
+use crate::model::{
+    vote, Action, AuditEntry, AuditHead, BallotTree, DelegationGraph, HistoryEntry,
+    Method, Normalization, ResultView, Settable, SettingView, TallyResult, Vote, VoteError,
+};
+use crate::results::ResultCache;
+
+/// what a user may do in one topic, each role can do what the ones
+/// before it can. observers only watch, voters vote, moderators manage
+/// plans and voters, the owner also hands out roles and deletes the topic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Observer,
+    Voter,
+    Moderator,
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Observer => "observer",
+            Role::Voter => "voter",
+            Role::Moderator => "moderator",
+            Role::Owner => "owner",
+        }
+    }
+}
+
+/// a topic only ever moves forward: draft → open → closed → archived.
+/// plans and voters can be set up while it is a draft, votes are only
+/// taken while it is open.
+// the default is for topics stored before there were statuses,
+// they took votes so they stay open
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Draft,
+    #[default]
+    Open,
+    Closed,
+    Archived,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Draft => "draft",
+            Status::Open => "open",
+            Status::Closed => "closed",
+            Status::Archived => "archived",
+        }
+    }
+
+    pub fn next(&self) -> Option<Status> {
+        match self {
+            Status::Draft => Some(Status::Open),
+            Status::Open => Some(Status::Closed),
+            Status::Closed => Some(Status::Archived),
+            Status::Archived => None,
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Topic {
     id: String,
     title: String,
     description: String,
+    #[serde(default)]
+    pub owner: Option<String>,
+    // moderators and observers. voters live in the setting, the owner above
+    #[serde(default)]
+    roles: BTreeMap<String, Role>,
+    #[serde(default)]
+    pub status: Status,
+    #[serde(default)]
+    pub method: Method,
+    // unix seconds, the scheduler opens and closes the topic at these
+    #[serde(default)]
+    pub opens_at: Option<i64>,
+    #[serde(default)]
+    pub closes_at: Option<i64>,
     pub setting_hash: String,
     setting_prev_hash: String,
     setting: Setting,
     result: Option<PollResult>,
+    // calculated once when the topic closes and never touched again
+    #[serde(default)]
+    final_result: Option<PollResult>,
+    #[serde(default)]
+    closed_at: Option<i64>,
+    // the topic's own method, counted at close next to `final_result`
+    #[serde(default)]
+    final_tally: Option<TallyResult>,
+    // merkle root over the ballots at close, see `BallotTree`
+    #[serde(default)]
+    pub ballot_root: Option<String>,
+    // how many steps the history has, they are stored apart like the log
+    #[serde(default)]
+    history_seq: u64,
+    // the history as older topics kept it, moved out on their next change
+    #[serde(default, rename = "history", skip_serializing)]
+    inline_history: Vec<HistoryEntry>,
+    // the history entries the last `commit` added, for the caller to store
+    #[serde(skip)]
+    new_history: Vec<HistoryEntry>,
+    #[serde(default)]
+    audit: AuditHead,
+    // the setting before the first change of a topic without a log yet
+    #[serde(skip)]
+    unlogged: Option<serde_json::Value>,
+    // the entry `commit` started the log with, for the caller to store
+    #[serde(skip)]
+    genesis: Option<AuditEntry>,
 }
 
 impl Settable for Topic {
@@ -32,31 +137,325 @@ impl Settable for Topic {
 }
 
 impl Topic {
+    pub fn is_owner(&self, user_id: &str) -> bool {
+        self.owner.as_deref() == Some(user_id)
+    }
+
+    pub fn role_of(&self, user_id: &str) -> Option<Role> {
+        if self.is_owner(user_id) {
+            return Some(Role::Owner);
+        }
+
+        match self.roles.get(user_id) {
+            Some(role) => Some(*role),
+            None if self.is_voter(user_id) => Some(Role::Voter),
+            None => None,
+        }
+    }
+
+    /// everyone with a role, the owner included
+    pub fn roles(&self) -> BTreeMap<String, Role> {
+        let mut roles: BTreeMap<String, Role> = self
+            .voter_ids()
+            .into_iter()
+            .map(|x| (x, Role::Voter))
+            .collect();
+
+        roles.extend(self.roles.iter().map(|(x, role)| (x.to_owned(), *role)));
+
+        if let Some(owner) = &self.owner {
+            roles.insert(owner.to_owned(), Role::Owner);
+        }
+
+        roles
+    }
+
+    /// replaces whatever role the user had. granting `Owner` hands the
+    /// topic over, the previous owner stays on as a moderator.
+    pub fn grant(&mut self, user_id: &str, role: Role) {
+        self.before_change();
+
+        match role {
+            Role::Owner => {
+                if let Some(previous) = self.owner.replace(user_id.to_string()) {
+                    if previous != user_id {
+                        self.roles.insert(previous, Role::Moderator);
+                    }
+                }
+                self.roles.remove(user_id);
+            }
+            Role::Moderator => {
+                self.roles.insert(user_id.to_string(), role);
+            }
+            Role::Voter => {
+                self.roles.remove(user_id);
+                self.setting.add_voter(user_id);
+            }
+            Role::Observer => {
+                self.roles.insert(user_id.to_string(), role);
+                self.setting.delete_voter(user_id);
+            }
+        }
+    }
+
+    /// the owner can't be revoked, only replaced through `grant`
+    pub fn revoke(&mut self, user_id: &str) {
+        self.before_change();
+        self.roles.remove(user_id);
+        self.setting.delete_voter(user_id);
+    }
+
+    pub fn accepts_votes(&self) -> bool {
+        self.status == Status::Open
+    }
+
+    pub fn accepts_changes(&self) -> bool {
+        matches!(self.status, Status::Draft | Status::Open)
+    }
+
+    /// moves the topic to `status` if that is the next step, returns false
+    /// otherwise. closing freezes the result of the last setting.
+    pub fn set_status(&mut self, status: Status) -> bool {
+        if self.status.next() != Some(status) {
+            return false;
+        }
+
+        if status == Status::Closed {
+            self.final_result = Some(self.setting.calculate());
+            self.final_tally = Some(self.tally(self.method));
+            self.closed_at = Some(Utc::now().timestamp());
+            self.ballot_root = self.ballot_tree().root();
+        }
+
+        self.status = status;
+        true
+    }
+
+    pub fn set_schedule(&mut self, schedule: Schedule) {
+        self.opens_at = schedule.opens_at;
+        self.closes_at = schedule.closes_at;
+    }
+
+    /// whether `apply_schedule` would change anything
+    pub fn is_due(&self, now: i64) -> bool {
+        match self.status {
+            Status::Draft => matches!(self.opens_at, Some(x) if x <= now),
+            Status::Open => matches!(self.closes_at, Some(x) if x <= now),
+            _ => false,
+        }
+    }
+
+    /// opens or closes the topic if `now` is past its schedule,
+    /// returns true if the status changed
+    pub fn apply_schedule(&mut self, now: i64) -> bool {
+        let mut moved = false;
+
+        // a draft whose closing time has passed too opens and closes at once
+        while self.is_due(now) {
+            match self.status.next() {
+                Some(next) => moved |= self.set_status(next),
+                None => break,
+            }
+        }
+
+        moved
+    }
+
+    // every change to the setting goes through here first, so a log that
+    // starts with it can start from what was there before
+    fn before_change(&mut self) {
+        if self.audit.seq == 0 && self.unlogged.is_none() {
+            let setting = serde_json::to_value(&self.setting).expect("Setting should be Serializable");
+            self.unlogged = Some(setting);
+        }
+    }
+
     pub fn add_plan_id(&mut self, plan_id: &str) {
+        self.before_change();
         self.setting.add_plan(plan_id);
     }
 
     pub fn remove_plan_id(&mut self, plan_id: &str) {
+        self.before_change();
         self.setting.delete_plan(plan_id);
     }
 
     pub fn add_user(&mut self, user_id: String) {
+        self.before_change();
+        // an observer that gets to vote isn't an observer any more
+        if self.roles.get(&user_id) == Some(&Role::Observer) {
+            self.roles.remove(&user_id);
+        }
         self.setting.add_voter(&user_id);
     }
 
     pub fn remove_user(&mut self, user_id: String) {
+        self.before_change();
         self.setting.delete_voter(&user_id);
     }
 
+    pub fn plan_ids(&self) -> Vec<String> {
+        SettingView::from(&self.setting).plans.into_iter().collect()
+    }
+
+    pub fn voter_ids(&self) -> Vec<String> {
+        SettingView::from(&self.setting).voters.into_iter().collect()
+    }
+
     pub fn setting_json(&self) -> Vec<u8> {
         serde_json::to_vec(&self.setting)
             .expect("Topic's Setting should be able to be Serialized")
     }
 
-    pub fn insert_vote(&mut self, user_id: &str, vote: BTreeMap<String, f64>) -> String {
+    pub fn is_voter(&self, user_id: &str) -> bool {
+        self.voter_ids().iter().any(|x| x == user_id)
+    }
+
+    /// see `vote::validate`
+    pub fn check_vote(
+        &self,
+        user_id: &str,
+        vote: Vote,
+        normalization: Normalization,
+    ) -> Result<Vote, VoteError> {
+        let view = SettingView::from(&self.setting);
+        vote::validate(user_id, vote, &view.plans, &view.voters, normalization)
+    }
+
+    /// a copy of the setting with `votes` in place of the stored ones,
+    /// for trying them out
+    pub fn with_votes(&self, votes: BTreeMap<String, Vote>) -> Setting {
+        let mut setting: Setting = serde_json::to_value(&self.setting)
+            .and_then(serde_json::from_value)
+            .expect("Setting should survive a round trip");
+
+        for (voter, vote) in votes {
+            setting.overwrite_vote(&voter, vote);
+        }
+
+        setting
+    }
+
+    pub fn insert_vote(&mut self, user_id: &str, vote: Vote) {
+        self.before_change();
         // more like swapping the HashMap
         self.setting.overwrite_vote(user_id, vote);
-        self.setting.based_hash()
+    }
+
+    /// call after changing the setting. if it really changed, moves on to
+    /// the new hash, recalculates, records the change in the history and
+    /// returns the audit entry for it. `None` if the setting is the same.
+    /// the first change also starts the log, see `genesis`
+    pub fn commit(
+        &mut self,
+        by: Option<&str>,
+        action: Action,
+        results: &ResultCache,
+    ) -> Option<AuditEntry> {
+        let hash = self.setting.based_hash();
+
+        if hash == self.setting_hash {
+            return None;
+        }
+
+        let at = Utc::now().timestamp();
+
+        self.genesis = None;
+        self.new_history.clear();
+        if self.audit.seq == 0 {
+            let before = self
+                .unlogged
+                .take()
+                .unwrap_or_else(|| serde_json::to_value(Setting::new()).expect("Setting should be Serializable"));
+            let before_hash = serde_json::from_value::<Setting>(before.to_owned())
+                .expect("Setting should survive a round trip")
+                .based_hash();
+            let genesis = Action::Genesis { setting: before };
+            self.genesis = Some(AuditEntry::append(&mut self.audit, &self.id, at, by, genesis, &before_hash));
+        }
+
+        if let Some(change) = action.change() {
+            let mut added = std::mem::take(&mut self.inline_history);
+            added.push(HistoryEntry {
+                topic_id: String::new(),
+                seq: 0,
+                hash: hash.to_owned(),
+                prev_hash: self.setting_hash.to_owned(),
+                at,
+                by: by.map(str::to_string),
+                change,
+            });
+
+            for mut entry in added {
+                self.history_seq += 1;
+                entry.topic_id = self.id.to_owned();
+                entry.seq = self.history_seq;
+                self.new_history.push(entry);
+            }
+        }
+
+        let entry = AuditEntry::append(&mut self.audit, &self.id, at, by, action, &hash);
+
+        self.update_setting_hash(&hash);
+        self.calculate(results);
+        Some(entry)
+    }
+
+    /// built from the current setting, which no longer changes once
+    /// the topic is closed
+    pub fn ballot_tree(&self) -> BallotTree {
+        BallotTree::new(&SettingView::from(&self.setting).votes)
+    }
+
+    /// the current ballots counted with `method`, see `Method`
+    pub fn tally(&self, method: Method) -> TallyResult {
+        method.tally().tally(&self.setting)
+    }
+
+    pub fn setting_view(&self) -> SettingView {
+        SettingView::from(&self.setting)
+    }
+
+    /// the last result, calculated if there is none yet
+    pub fn result_view(&self) -> ResultView {
+        match &self.result {
+            Some(result) => ResultView::from(result),
+            None => ResultView::from(&self.setting.calculate()),
+        }
+    }
+
+    /// the current setting, annotated with the last result
+    pub fn delegation_graph(&self) -> DelegationGraph {
+        DelegationGraph::new(&SettingView::from(&self.setting), &self.result_view())
+    }
+
+    pub fn closed_at(&self) -> Option<i64> {
+        self.closed_at
+    }
+
+    /// the first entry of the log, if the last `commit` started it
+    pub fn genesis(&self) -> Option<&AuditEntry> {
+        self.genesis.as_ref()
+    }
+
+    pub fn audit_head(&self) -> &AuditHead {
+        &self.audit
+    }
+
+    /// how many history entries are stored for the topic
+    pub fn history_seq(&self) -> u64 {
+        self.history_seq
+    }
+
+    /// what an older topic still keeps of its history itself, oldest first
+    pub fn inline_history(&self) -> &[HistoryEntry] {
+        &self.inline_history
+    }
+
+    /// the history entries the last `commit` added, older ones of the
+    /// topic's own included
+    pub fn new_history(&self) -> &[HistoryEntry] {
+        &self.new_history
     }
 
     pub fn update_setting_hash(&mut self, new_hash: &str) {
@@ -64,33 +463,195 @@ impl Topic {
         self.setting_hash = new_hash.to_string();
     }
 
-    pub fn calculate(&mut self) {
-        let result = self.setting.calculate();
+    /// settings come back to earlier hashes (a vote undone, a voter
+    /// removed and added again), those results are looked up
+    pub fn calculate(&mut self, results: &ResultCache) {
+        let result = results.calculate(&self.setting);
         self.result = Some(result);
     }
+
+    pub fn result(&self) -> Option<&PollResult> {
+        self.result.as_ref()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Schedule {
+    #[serde(default)]
+    pub opens_at: Option<i64>,
+    #[serde(default)]
+    pub closes_at: Option<i64>,
+}
+
+impl Schedule {
+    pub fn is_valid(&self) -> bool {
+        match (self.opens_at, self.closes_at) {
+            (Some(opens_at), Some(closes_at)) => opens_at < closes_at,
+            _ => true,
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
 pub struct PartialTopic {
     title: String,
     description: String,
+    #[serde(default)]
+    opens_at: Option<i64>,
+    #[serde(default)]
+    closes_at: Option<i64>,
+    #[serde(default)]
+    method: Method,
+}
+
+impl PartialTopic {
+    pub fn schedule(&self) -> Schedule {
+        Schedule {
+            opens_at: self.opens_at,
+            closes_at: self.closes_at,
+        }
+    }
 }
 
 impl From<PartialTopic> for Topic {
     fn from(p_topic: PartialTopic) -> Self {
         let cat = format!("{}{}", p_topic.title, p_topic.description);
-        let id = encode(Sha256::digest(&cat.as_bytes())).into_string();
+        let id = encode(Sha256::digest(cat.as_bytes())).into_string();
 
         Self {
             id,
             title: p_topic.title,
             description: p_topic.description,
+            owner: None,
+            roles: BTreeMap::new(),
+            status: Status::Draft,
+            method: p_topic.method,
+            opens_at: p_topic.opens_at,
+            closes_at: p_topic.closes_at,
             setting_hash: "0".to_string(),
             setting: Setting::new(),
             setting_prev_hash: "0".to_string(),
             result: None,
+            final_result: None,
+            closed_at: None,
+            final_tally: None,
+            ballot_root: None,
+            history_seq: 0,
+            inline_history: Vec::new(),
+            new_history: Vec::new(),
+            audit: AuditHead::default(),
+            unlogged: None,
+            genesis: None,
         }
     }
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topic() -> Topic {
+        let partial: PartialTopic =
+            serde_json::from_value(serde_json::json!({"title": "t", "description": "d"})).unwrap();
+        partial.into()
+    }
+
+    #[test]
+    fn roles_replace_each_other() {
+        let mut topic = topic();
+        topic.grant("alice", Role::Owner);
+        topic.add_user("bob".into());
+        topic.grant("carol", Role::Observer);
+
+        assert_eq!(topic.role_of("alice"), Some(Role::Owner));
+        assert_eq!(topic.role_of("bob"), Some(Role::Voter));
+        assert_eq!(topic.role_of("carol"), Some(Role::Observer));
+        assert_eq!(topic.role_of("dave"), None);
+
+        // observers that get to vote stop observing
+        topic.grant("carol", Role::Voter);
+        assert_eq!(topic.role_of("carol"), Some(Role::Voter));
+
+        // and voters that only observe leave the setting
+        topic.grant("bob", Role::Observer);
+        assert!(!topic.is_voter("bob"));
+    }
+
+    #[test]
+    fn handing_over_keeps_the_previous_owner_as_moderator() {
+        let mut topic = topic();
+        topic.grant("alice", Role::Owner);
+        topic.grant("bob", Role::Owner);
+
+        assert_eq!(topic.role_of("bob"), Some(Role::Owner));
+        assert_eq!(topic.role_of("alice"), Some(Role::Moderator));
+    }
+
+    #[test]
+    fn revoking_a_moderator_who_votes_removes_both() {
+        let mut topic = topic();
+        topic.add_user("bob".into());
+        topic.grant("bob", Role::Moderator);
+        assert_eq!(topic.role_of("bob"), Some(Role::Moderator));
+        assert!(topic.is_voter("bob"));
+
+        topic.revoke("bob");
+        assert_eq!(topic.role_of("bob"), None);
+    }
+
+    #[test]
+    fn commit_only_records_real_changes() {
+        let results = ResultCache::new(10);
+        let mut topic = topic();
+
+        topic.add_user("bob".into());
+        let entry = topic.commit(None, Action::AddVoter { voter: "bob".into() }, &results);
+        assert!(entry.is_some());
+        assert!(topic.result().is_some());
+        assert_eq!(topic.history_seq(), 1);
+        assert_eq!(topic.new_history()[0].id(), format!("{}:1", topic.id()));
+
+        topic.add_user("bob".into());
+        assert!(topic.commit(None, Action::AddVoter { voter: "bob".into() }, &results).is_none());
+        assert_eq!(topic.history_seq(), 1);
+    }
+
+    #[test]
+    fn inline_history_moves_out_with_the_next_change() {
+        let results = ResultCache::new(10);
+        let mut json = serde_json::to_value(topic()).unwrap();
+        json["history"] = serde_json::json!([{
+            "hash": "h",
+            "prev_hash": "0",
+            "at": 0,
+            "by": null,
+            "change": {"type": "add_plan", "plan_id": "rice"},
+        }]);
+        let mut topic: Topic = serde_json::from_value(json).unwrap();
+        assert_eq!(topic.inline_history().len(), 1);
+
+        topic.add_user("bob".into());
+        topic.commit(None, Action::AddVoter { voter: "bob".into() }, &results);
+
+        let seqs: Vec<u64> = topic.new_history().iter().map(|x| x.seq).collect();
+        assert_eq!(seqs, vec![1, 2]);
+        assert!(topic.inline_history().is_empty());
+        assert!(serde_json::to_value(&topic).unwrap().get("history").is_none());
+    }
+
+    #[test]
+    fn changes_stop_at_close() {
+        let mut topic = topic();
+        assert!(topic.accepts_changes() && !topic.accepts_votes());
+
+        assert!(!topic.set_status(Status::Closed));
+        assert!(topic.set_status(Status::Open));
+        assert!(topic.accepts_changes() && topic.accepts_votes());
+
+        assert!(topic.set_status(Status::Closed));
+        assert!(!topic.accepts_changes() && !topic.accepts_votes());
+        assert!(topic.closed_at().is_some());
+    }
+}
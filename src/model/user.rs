@@ -41,7 +41,7 @@ pub struct User {
 impl User {
     pub fn new(nickname: String, email: String) -> Self {
         let cat = format!("email:{}", &email);
-        let id = encode(Sha256::digest(&cat.as_bytes())).into_string();
+        let id = encode(Sha256::digest(cat.as_bytes())).into_string();
 
         Self {
             id,
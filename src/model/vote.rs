@@ -0,0 +1,170 @@
+use crate::model::FieldError;
+use dotenv::dotenv;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// weights per plan or per voter delegated to, as liq takes them
+pub type Vote = BTreeMap<String, f64>;
+
+/// what to do with a vote whose weights add up to more than 1.0.
+/// set with `VOTE_NORMALIZATION`, `reject` if unset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Normalization {
+    /// store the weights as they are
+    None,
+    /// refuse the vote
+    Reject,
+    /// scale the weights down so they add up to 1.0
+    Scale,
+}
+
+impl Normalization {
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        match std::env::var("VOTE_NORMALIZATION").as_deref() {
+            Ok("none") => Normalization::None,
+            Ok("scale") => Normalization::Scale,
+            _ => Normalization::Reject,
+        }
+    }
+}
+
+// weights that add up to 1 in decimal rarely do in floating point,
+// 0.1 + 0.2 + 0.7 is 1.0000000000000002
+const SUM_TOLERANCE: f64 = 1e-9;
+
+#[derive(Debug)]
+pub struct VoteError(pub Vec<FieldError>);
+
+impl std::fmt::Display for VoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fields: Vec<String> = self
+            .0
+            .iter()
+            .map(|e| format!("{}: {}", e.field, e.message))
+            .collect();
+        write!(f, "invalid vote ({})", fields.join("; "))
+    }
+}
+
+/// checks `vote` of `voter` against the plans and voters of a setting.
+/// every key has to be a plan or another voter to delegate to, every
+/// weight finite and not negative. returns the vote normalized.
+pub fn validate(
+    voter: &str,
+    vote: Vote,
+    plans: &BTreeSet<String>,
+    voters: &BTreeSet<String>,
+    normalization: Normalization,
+) -> Result<Vote, VoteError> {
+    let mut errors = Vec::new();
+    let mut fail = |field: &str, message: String| {
+        errors.push(FieldError {
+            field: field.to_string(),
+            message,
+        })
+    };
+
+    for (key, weight) in vote.iter() {
+        if key == voter {
+            fail(key, "cannot delegate to yourself".to_string());
+        } else if !plans.contains(key) && !voters.contains(key) {
+            fail(key, "is neither a plan nor a voter of this topic".to_string());
+        }
+
+        if !weight.is_finite() {
+            fail(key, "weight has to be a finite number".to_string());
+        } else if *weight < 0.0 {
+            fail(key, format!("weight has to be 0 or more, got {}", weight));
+        }
+    }
+
+    let sum: f64 = vote.values().filter(|x| x.is_finite()).sum();
+
+    let over = sum > 1.0 + SUM_TOLERANCE;
+
+    if over && normalization == Normalization::Reject {
+        fail("vote", format!("weights add up to {}, at most 1 is allowed", sum));
+    }
+
+    if !errors.is_empty() {
+        return Err(VoteError(errors));
+    }
+
+    match normalization {
+        Normalization::Scale if over => Ok(vote
+            .into_iter()
+            .map(|(key, weight)| (key, weight / sum))
+            .collect()),
+        _ => Ok(vote),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(keys: &[&str]) -> BTreeSet<String> {
+        keys.iter().map(|x| x.to_string()).collect()
+    }
+
+    fn vote(weights: &[(&str, f64)]) -> Vote {
+        weights.iter().map(|(k, w)| (k.to_string(), *w)).collect()
+    }
+
+    fn fields(result: Result<Vote, VoteError>) -> Vec<String> {
+        result.unwrap_err().0.into_iter().map(|e| e.field).collect()
+    }
+
+    #[test]
+    fn plans_and_other_voters_are_fine() {
+        let checked = validate(
+            "a",
+            vote(&[("p", 0.5), ("b", 0.5)]),
+            &set(&["p"]),
+            &set(&["a", "b"]),
+            Normalization::Reject,
+        );
+        assert_eq!(checked.unwrap(), vote(&[("p", 0.5), ("b", 0.5)]));
+    }
+
+    #[test]
+    fn bad_keys_and_weights_are_listed() {
+        let checked = validate(
+            "a",
+            vote(&[("a", 0.1), ("x", 0.1), ("p", -1.0), ("b", f64::NAN)]),
+            &set(&["p"]),
+            &set(&["a", "b"]),
+            Normalization::None,
+        );
+        assert_eq!(fields(checked), vec!["a", "b", "p", "x"]);
+    }
+
+    #[test]
+    fn weights_over_one() {
+        let plans = set(&["p", "q"]);
+        let voters = set(&["a"]);
+        let heavy = || vote(&[("p", 1.5), ("q", 0.5)]);
+
+        let rejected = validate("a", heavy(), &plans, &voters, Normalization::Reject);
+        assert_eq!(fields(rejected), vec!["vote"]);
+
+        let kept = validate("a", heavy(), &plans, &voters, Normalization::None);
+        assert_eq!(kept.unwrap(), heavy());
+
+        let scaled = validate("a", heavy(), &plans, &voters, Normalization::Scale);
+        assert_eq!(scaled.unwrap(), vote(&[("p", 0.75), ("q", 0.25)]));
+    }
+
+    #[test]
+    fn a_sum_of_one_in_floating_point_is_one() {
+        let plans = set(&["p", "q", "r"]);
+        let tenths = || vote(&[("p", 0.1), ("q", 0.2), ("r", 0.7)]);
+
+        let checked = validate("a", tenths(), &plans, &set(&["a"]), Normalization::Reject);
+        assert_eq!(checked.unwrap(), tenths());
+
+        // and isn't scaled either
+        let checked = validate("a", tenths(), &plans, &set(&["a"]), Normalization::Scale);
+        assert_eq!(checked.unwrap(), tenths());
+    }
+}
@@ -0,0 +1,162 @@
+use crate::model::Settable;
+use hmac::{Hmac, Mac, NewMac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::BTreeSet;
+
+/// how many deliveries a webhook remembers, oldest go first
+const DELIVERY_LOG_LEN: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// only reaches webhooks registered for every topic
+    TopicCreated,
+    TopicOpened,
+    TopicClosed,
+    PlanAdded,
+    PlanRemoved,
+    VoterAdded,
+    VoterRemoved,
+    ResultChanged,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::TopicCreated => "topic_created",
+            EventKind::TopicOpened => "topic_opened",
+            EventKind::TopicClosed => "topic_closed",
+            EventKind::PlanAdded => "plan_added",
+            EventKind::PlanRemoved => "plan_removed",
+            EventKind::VoterAdded => "voter_added",
+            EventKind::VoterRemoved => "voter_removed",
+            EventKind::ResultChanged => "result_changed",
+        }
+    }
+}
+
+/// what gets posted, signed, to a webhook's url
+#[derive(Debug, Serialize)]
+pub struct Event {
+    pub id: String,
+    pub kind: EventKind,
+    pub topic_id: String,
+    pub at: i64,
+    pub data: serde_json::Value,
+}
+
+/// one attempt at delivering an event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delivery {
+    pub event_id: String,
+    pub kind: EventKind,
+    pub attempt: u32,
+    pub at: i64,
+    pub ok: bool,
+    /// the http status, if there was an answer at all
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+/// an url that is told about the events of one topic, or of every topic
+/// if `topic_id` is `None`. stored under `webhook:{id}` and listed in
+/// `webhooks:{topic_id}`, `webhooks:*` for the ones without a topic.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Webhook {
+    pub id: String,
+    pub topic_id: Option<String>,
+    pub url: String,
+    /// every event if empty
+    pub events: BTreeSet<EventKind>,
+    pub created_at: i64,
+    secret: String,
+    #[serde(default)]
+    deliveries: Vec<Delivery>,
+}
+
+/// a webhook as its owner sees it, without the secret
+#[derive(Debug, Serialize)]
+pub struct WebhookInfo<'a> {
+    pub id: &'a str,
+    pub topic_id: Option<&'a str>,
+    pub url: &'a str,
+    pub events: &'a BTreeSet<EventKind>,
+    pub created_at: i64,
+}
+
+impl Webhook {
+    pub fn new(
+        id: String,
+        topic_id: Option<String>,
+        url: String,
+        events: BTreeSet<EventKind>,
+        secret: String,
+        created_at: i64,
+    ) -> Self {
+        Self {
+            id,
+            topic_id,
+            url,
+            events,
+            created_at,
+            secret,
+            deliveries: Vec::new(),
+        }
+    }
+
+    /// the set listing the webhooks of `topic_id`
+    pub fn set_of(topic_id: Option<&str>) -> String {
+        format!("webhooks:{}", topic_id.unwrap_or("*"))
+    }
+
+    pub fn wants(&self, kind: EventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+
+    /// hex HMAC-SHA256 of `body` with the webhook's secret. receivers
+    /// compute the same over the raw body to know it came from us
+    pub fn sign(&self, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_varkey(self.secret.as_bytes())
+            .expect("HMAC takes keys of any length");
+        mac.update(body);
+        format!("{:x}", mac.finalize().into_bytes())
+    }
+
+    pub fn log(&mut self, delivery: Delivery) {
+        self.deliveries.push(delivery);
+        if self.deliveries.len() > DELIVERY_LOG_LEN {
+            let over = self.deliveries.len() - DELIVERY_LOG_LEN;
+            self.deliveries.drain(..over);
+        }
+    }
+
+    /// newest last
+    pub fn deliveries(&self) -> &[Delivery] {
+        &self.deliveries
+    }
+
+    pub fn info(&self) -> WebhookInfo<'_> {
+        WebhookInfo {
+            id: &self.id,
+            topic_id: self.topic_id.as_deref(),
+            url: &self.url,
+            events: &self.events,
+            created_at: self.created_at,
+        }
+    }
+}
+
+impl Settable for Webhook {
+    fn domain_prefix() -> String {
+        String::from("webhook")
+    }
+
+    fn id(&self) -> String {
+        self.id.to_string()
+    }
+
+    fn list_item(&self) -> String {
+        self.id()
+    }
+}
@@ -0,0 +1,360 @@
+use crate::auth::generate_access_token;
+use crate::model::{Action, AuditEntry, Delivery, Event, EventKind, Settable, Topic, Webhook};
+use crate::store::{self, Store, StoreError};
+use actix_web::client::Client;
+use actix_web::web::{self, Bytes};
+use chrono::Utc;
+use dotenv::dotenv;
+use futures::future::join_all;
+use serde_json::json;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use url::{Host, Url};
+
+const MAX_ATTEMPTS: u32 = 5;
+// doubled after every failed attempt: 2s, 4s, 8s, 16s
+const FIRST_BACKOFF: Duration = Duration::from_secs(2);
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// tells the webhooks of the topic, and the ones for every topic, about
+/// `kind`. returns right away, delivering happens in the background.
+pub fn fire(store: &web::Data<dyn Store>, topic_id: &str, kind: EventKind, data: serde_json::Value) {
+    let store = store.clone();
+    let event = Event {
+        id: generate_access_token(),
+        kind,
+        topic_id: topic_id.to_owned(),
+        at: Utc::now().timestamp(),
+        data,
+    };
+
+    actix_web::rt::spawn(async move {
+        if let Err(e) = deliver_all(&store, &event).await {
+            log::error!("could not deliver {} of topic:{}: {}", event.kind.as_str(), event.topic_id, e);
+        }
+    });
+}
+
+/// the events of a change committed to `topic`: what changed, and the
+/// result that came out of it
+pub fn fire_committed(store: &web::Data<dyn Store>, topic: &Topic, entry: &AuditEntry) {
+    let topic_id = topic.id();
+
+    let change = match &entry.action {
+        Action::AddPlan { plan_id } => Some((EventKind::PlanAdded, json!({ "plan_id": plan_id }))),
+        Action::RemovePlan { plan_id } => Some((EventKind::PlanRemoved, json!({ "plan_id": plan_id }))),
+        Action::AddVoter { voter } => Some((EventKind::VoterAdded, json!({ "voter": voter }))),
+        Action::RemoveVoter { voter } => Some((EventKind::VoterRemoved, json!({ "voter": voter }))),
+        // the result says it all
+        Action::Vote { .. } | Action::Genesis { .. } => None,
+    };
+
+    if let Some((kind, data)) = change {
+        fire(store, &topic_id, kind, data);
+    }
+
+    fire(
+        store,
+        &topic_id,
+        EventKind::ResultChanged,
+        json!({ "setting_hash": topic.setting_hash, "result": topic.result_view() }),
+    );
+}
+
+pub fn fire_opened(store: &web::Data<dyn Store>, topic: &Topic) {
+    fire(store, &topic.id(), EventKind::TopicOpened, json!({ "closes_at": topic.closes_at }));
+}
+
+pub fn fire_closed(store: &web::Data<dyn Store>, topic: &Topic) {
+    fire(
+        store,
+        &topic.id(),
+        EventKind::TopicClosed,
+        json!({ "closed_at": topic.closed_at(), "result": topic.result_view() }),
+    );
+}
+
+async fn deliver_all(store: &web::Data<dyn Store>, event: &Event) -> Result<(), StoreError> {
+    let mut ids = Vec::new();
+    for set in [Webhook::set_of(Some(&event.topic_id)), Webhook::set_of(None)].iter() {
+        for id in store.members(set).await? {
+            ids.push(String::from_utf8_lossy(&id).into_owned());
+        }
+    }
+
+    let webhooks = store::get_all::<Webhook>(&ids, &Webhook::domain_prefix(), store).await?;
+    let body = Bytes::from(serde_json::to_vec(event).expect("Event should be Serializable"));
+
+    join_all(
+        webhooks
+            .iter()
+            .filter(|(_, webhook)| webhook.wants(event.kind))
+            .map(|(_, webhook)| deliver(store, webhook, event, body.clone())),
+    )
+    .await;
+
+    Ok(())
+}
+
+/// per-topic webhooks are anyone's who owns a topic, they must not point
+/// into the network we run in. the host has to resolve to public addresses
+/// only, unless it's listed in `WEBHOOK_ALLOWED_HOSTS` (comma separated).
+/// returns the address to connect to, so the name isn't looked up again
+/// in between, `None` for allowed hosts
+pub async fn check_target(url: &str) -> Result<Option<SocketAddr>, String> {
+    dotenv().ok();
+    let allowed: Vec<String> = std::env::var("WEBHOOK_ALLOWED_HOSTS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect();
+
+    vet_target(url, &allowed, |host, port| async move {
+        Ok(tokio::net::lookup_host((host.as_str(), port)).await?.collect())
+    })
+    .await
+}
+
+// `check_target` with the allowed hosts and the resolver handed in
+async fn vet_target<F, R>(url: &str, allowed: &[String], resolve: F) -> Result<Option<SocketAddr>, String>
+where
+    F: FnOnce(String, u16) -> R,
+    R: Future<Output = io::Result<Vec<SocketAddr>>>,
+{
+    let url = Url::parse(url).map_err(|e| format!("{} is not an url: {}", url, e))?;
+    let host = url.host_str().unwrap_or_default();
+    let port = url.port_or_known_default().unwrap_or(80);
+
+    if allowed.iter().any(|x| x.eq_ignore_ascii_case(host)) {
+        return Ok(None);
+    }
+
+    let addresses: Vec<SocketAddr> = match url.host() {
+        Some(Host::Ipv4(ip)) => vec![SocketAddr::new(IpAddr::V4(ip), port)],
+        Some(Host::Ipv6(ip)) => vec![SocketAddr::new(IpAddr::V6(ip), port)],
+        Some(Host::Domain(domain)) => resolve(domain.to_string(), port)
+            .await
+            .map_err(|e| format!("{} does not resolve: {}", domain, e))?,
+        None => return Err(format!("{} has no host", url)),
+    };
+
+    match addresses.iter().find(|x| !is_public(x.ip())) {
+        Some(x) => Err(format!("{} is at {}, which is not a public address", host, x.ip())),
+        None => match addresses.first() {
+            Some(x) => Ok(Some(*x)),
+            None => Err(format!("{} does not resolve", host)),
+        },
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_public_v4(v4),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "this network"
+        || a == 0
+        // carrier-grade nat
+        || (a == 100 && (64..128).contains(&b))
+        // protocol assignments
+        || (a == 192 && b == 0 && c == 0)
+        // benchmarking
+        || (a == 198 && (b == 18 || b == 19))
+        // reserved, broadcast included
+        || a >= 240)
+}
+
+// the ranges that embed or translate to ipv4 are refused as a whole,
+// they can carry 127.0.0.1 as well as anything else
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let s = ip.segments();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // ipv4-compatible ::a.b.c.d
+        || s[..6] == [0; 6]
+        // nat64 64:ff9b::/96 and its local use 64:ff9b:1::/48
+        || (s[0] == 0x64 && s[1] == 0xff9b)
+        // teredo 2001::/32 and documentation 2001:db8::/32
+        || (s[0] == 0x2001 && (s[1] == 0 || s[1] == 0xdb8))
+        // 6to4
+        || s[0] == 0x2002
+        // unique local fc00::/7 and link-local fe80::/10
+        || (s[0] & 0xfe00) == 0xfc00
+        || (s[0] & 0xffc0) == 0xfe80)
+}
+
+/// posts `body` until the receiver answers with a 2xx or we run out of
+/// attempts. every attempt goes into the webhook's delivery log
+async fn deliver(store: &web::Data<dyn Store>, webhook: &Webhook, event: &Event, body: Bytes) {
+    // a redirect would lead around `check_target`
+    let client = Client::builder().timeout(TIMEOUT).disable_redirects().finish();
+    let signature = format!("sha256={}", webhook.sign(&body));
+    let mut backoff = FIRST_BACKOFF;
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        // names can point elsewhere by now than when the webhook was made.
+        // the request goes to the address that was checked, not to
+        // whatever the name resolves to when connecting
+        let target = match webhook.topic_id {
+            Some(_) => check_target(&webhook.url).await,
+            None => Ok(None),
+        };
+
+        let sent = match target {
+            Ok(address) => {
+                let request = client
+                    .post(&webhook.url)
+                    .header("Content-Type", "application/json")
+                    .header("X-Ornot-Event", event.kind.as_str())
+                    .header("X-Ornot-Delivery", event.id.as_str())
+                    .header("X-Ornot-Signature", signature.as_str());
+
+                match address {
+                    Some(x) => request.address(x),
+                    None => request,
+                }
+                .send_body(body.clone())
+                .await
+                .map_err(|e| e.to_string())
+            }
+            Err(e) => Err(e),
+        };
+
+        let (ok, status, error) = match sent {
+            Ok(response) if response.status().is_success() => (true, Some(response.status()), None),
+            Ok(response) => (
+                false,
+                Some(response.status()),
+                Some(format!("answered {}", response.status())),
+            ),
+            Err(e) => (false, None, Some(e)),
+        };
+
+        let delivery = Delivery {
+            event_id: event.id.to_owned(),
+            kind: event.kind,
+            attempt,
+            at: Utc::now().timestamp(),
+            ok,
+            status: status.map(|x| x.as_u16()),
+            error,
+        };
+
+        // a lost log line is no reason to deliver again
+        let logged = store::modify(&webhook.id, store, |webhook: &mut Webhook| {
+            webhook.log(delivery.clone());
+        })
+        .await;
+        if let Err(e) = logged {
+            log::error!("could not log a delivery of webhook:{}: {}", webhook.id, e);
+        }
+
+        if ok {
+            return;
+        }
+
+        if attempt < MAX_ATTEMPTS {
+            actix_web::rt::time::delay_for(backoff).await;
+            backoff *= 2;
+        }
+    }
+
+    log::warn!(
+        "gave up delivering {} to webhook:{} after {} attempts",
+        event.id,
+        webhook.id,
+        MAX_ATTEMPTS
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_public_addresses_are_public() {
+        let inside = [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "100.64.0.1",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::127.0.0.1",
+            "198.18.0.1",
+            "198.19.255.255",
+            "240.0.0.1",
+            "255.255.255.255",
+            "192.0.0.8",
+            "64:ff9b::7f00:1",
+            "2002:7f00:1::1",
+            "2001:0:4136:e378::1",
+            "2001:db8::1",
+        ];
+        for ip in inside.iter() {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["1.1.1.1", "100.128.0.1", "198.20.0.1", "2606:4700::1111", "::ffff:8.8.8.8"].iter() {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    async fn vet(url: &str, allowed: &[&str], resolved: &[&str]) -> Result<Option<SocketAddr>, String> {
+        let allowed: Vec<String> = allowed.iter().map(|x| x.to_string()).collect();
+        let resolved: Vec<IpAddr> = resolved.iter().map(|x| x.parse().unwrap()).collect();
+
+        vet_target(url, &allowed, |_, port| async move {
+            Ok(resolved.into_iter().map(|ip| SocketAddr::new(ip, port)).collect())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn targets_inside_are_refused() {
+        assert!(vet("http://127.0.0.1:8080/hook", &[], &[]).await.is_err());
+        assert!(vet("http://[::1]/hook", &[], &[]).await.is_err());
+        assert!(vet("http://hooks.example/hook", &[], &["10.0.0.1"]).await.is_err());
+        assert!(vet("http://hooks.example/hook", &[], &[]).await.is_err());
+        // one address inside is enough
+        assert!(vet("http://hooks.example/hook", &[], &["1.1.1.1", "127.0.0.1"]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn the_checked_address_is_the_one_to_connect_to() {
+        let target = vet("https://hooks.example/hook", &[], &["1.1.1.1"]).await.unwrap();
+        assert_eq!(target, Some("1.1.1.1:443".parse().unwrap()));
+
+        let target = vet("http://1.1.1.1:8080/hook", &[], &[]).await.unwrap();
+        assert_eq!(target, Some("1.1.1.1:8080".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn allowed_hosts_go_unchecked() {
+        let allowed = ["hooks.internal", "LOCALHOST"];
+        assert_eq!(vet("http://localhost/hook", &allowed, &["127.0.0.1"]).await, Ok(None));
+        assert!(vet("http://other.internal/hook", &allowed, &["10.0.0.1"]).await.is_err());
+    }
+}
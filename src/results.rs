@@ -0,0 +1,217 @@
+use crate::store::{Store, StoreError};
+use actix_web::web;
+use dotenv::dotenv;
+use liq::{PollResult, Setting};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+const DEFAULT_CAPACITY: usize = 1000;
+// a past setting of a topic is calculated again if it's asked for after this
+const STORED_TTL: usize = 60 * 60 * 24 * 7;
+
+/// liq's results by setting hash, `result:{setting_hash}` in the store.
+/// a setting's result never changes, entries only ever fall out of the
+/// cache, never go stale. one is shared by all workers, the most recently
+/// used `RESULT_CACHE_SIZE` results are kept in memory. only the results of
+/// topics' settings go to the store, anyone can post a raw setting.
+pub struct ResultCache {
+    memory: Mutex<Lru>,
+}
+
+// results are kept as json, liq's don't clone
+struct Lru {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<String, (u64, Vec<u8>)>,
+    // tick of the last use to the key, the oldest goes first
+    order: BTreeMap<u64, String>,
+}
+
+impl Lru {
+    fn get(&mut self, key: &str) -> Option<Vec<u8>> {
+        self.tick += 1;
+        let tick = self.tick;
+
+        let (used, value) = self.entries.get_mut(key)?;
+        self.order.remove(used);
+        self.order.insert(tick, key.to_string());
+        *used = tick;
+
+        Some(value.to_owned())
+    }
+
+    fn put(&mut self, key: &str, value: Vec<u8>) {
+        self.tick += 1;
+
+        if let Some((used, _)) = self.entries.insert(key.to_string(), (self.tick, value)) {
+            self.order.remove(&used);
+        }
+        self.order.insert(self.tick, key.to_string());
+
+        while self.entries.len() > self.capacity {
+            let oldest = match self.order.keys().next() {
+                Some(x) => *x,
+                None => break,
+            };
+            if let Some(key) = self.order.remove(&oldest) {
+                self.entries.remove(&key);
+            }
+        }
+    }
+}
+
+fn domain(hash: &str) -> String {
+    format!("result:{}", hash)
+}
+
+fn to_json(result: &PollResult) -> Vec<u8> {
+    serde_json::to_vec(result).expect("PollResult should be Serializable")
+}
+
+impl ResultCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            memory: Mutex::new(Lru {
+                capacity: capacity.max(1),
+                tick: 0,
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+            }),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        let capacity = std::env::var("RESULT_CACHE_SIZE")
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(DEFAULT_CAPACITY);
+
+        Self::new(capacity)
+    }
+
+    fn remembered(&self, hash: &str) -> Option<PollResult> {
+        let json = self.memory.lock().unwrap().get(hash)?;
+        serde_json::from_slice(&json).ok()
+    }
+
+    fn remember(&self, hash: &str, result: &PollResult) {
+        self.memory.lock().unwrap().put(hash, to_json(result));
+    }
+
+    /// the result of `setting`, from memory or calculated. doesn't wait for
+    /// the store, so it also works inside `store::modify`
+    pub fn calculate(&self, setting: &Setting) -> PollResult {
+        let hash = setting.based_hash();
+
+        if let Some(result) = self.remembered(&hash) {
+            return result;
+        }
+
+        let result = setting.calculate();
+        self.remember(&hash, &result);
+        result
+    }
+
+    /// same as `calculate`, but looks in the store before calculating and
+    /// keeps what it calculated there
+    pub async fn calculate_stored(
+        &self,
+        setting: &Setting,
+        store: &web::Data<dyn Store>,
+    ) -> Result<PollResult, StoreError> {
+        let hash = setting.based_hash();
+
+        if let Some(result) = self.remembered(&hash) {
+            return Ok(result);
+        }
+
+        // a result that doesn't parse is as good as none, it's calculated again
+        if let Some(json) = store.get(&domain(&hash)).await? {
+            if let Ok(result) = serde_json::from_slice::<PollResult>(&json) {
+                self.memory.lock().unwrap().put(&hash, json);
+                return Ok(result);
+            }
+        }
+
+        let result = setting.calculate();
+        self.remember(&hash, &result);
+        keep(&hash, &result, store).await?;
+
+        Ok(result)
+    }
+}
+
+/// puts a result in the store, for ones calculated where it couldn't
+/// be waited for
+pub async fn keep(
+    hash: &str,
+    result: &PollResult,
+    store: &web::Data<dyn Store>,
+) -> Result<(), StoreError> {
+    store
+        .set_expiring(&domain(hash), &to_json(result), STORED_TTL)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lru(capacity: usize) -> Lru {
+        Lru {
+            capacity,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn keys(lru: &Lru) -> Vec<&str> {
+        lru.order.values().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn the_least_recently_used_goes_first() {
+        let mut lru = lru(2);
+        lru.put("a", b"1".to_vec());
+        lru.put("b", b"2".to_vec());
+
+        // reading a makes b the oldest
+        assert_eq!(lru.get("a"), Some(b"1".to_vec()));
+        lru.put("c", b"3".to_vec());
+
+        assert_eq!(keys(&lru), vec!["a", "c"]);
+        assert_eq!(lru.get("b"), None);
+    }
+
+    #[test]
+    fn putting_again_counts_as_a_use() {
+        let mut lru = lru(2);
+        lru.put("a", b"1".to_vec());
+        lru.put("b", b"2".to_vec());
+        lru.put("a", b"3".to_vec());
+        lru.put("c", b"4".to_vec());
+
+        assert_eq!(keys(&lru), vec!["a", "c"]);
+        assert_eq!(lru.get("a"), Some(b"3".to_vec()));
+        assert_eq!(lru.entries.len(), lru.order.len());
+    }
+
+    #[test]
+    fn misses_change_nothing() {
+        let mut lru = lru(1);
+        lru.put("a", b"1".to_vec());
+
+        assert_eq!(lru.get("b"), None);
+        assert_eq!(keys(&lru), vec!["a"]);
+    }
+
+    #[test]
+    fn a_cache_keeps_at_least_one() {
+        let cache = ResultCache::new(0);
+        cache.memory.lock().unwrap().put("a", b"1".to_vec());
+
+        assert_eq!(cache.memory.lock().unwrap().get("a"), Some(b"1".to_vec()));
+    }
+}
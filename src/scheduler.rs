@@ -0,0 +1,161 @@
+use crate::handlers::topic::status_moved;
+use crate::live::Live;
+use crate::model::Topic;
+use crate::store::{self, Store, StoreError};
+use actix::prelude::*;
+use actix_web::web;
+use chrono::Utc;
+use dotenv::dotenv;
+use std::time::Duration;
+
+// seconds between two sweeps
+const DEFAULT_INTERVAL: u64 = 30;
+
+/// opens and closes topics once their `opens_at` / `closes_at` have
+/// passed. closing calculates the final result and tells everyone, same
+/// as closing by hand. it also has the store drop expired keys. one is
+/// started per server, next to the workers.
+pub struct Scheduler {
+    store: web::Data<dyn Store>,
+    live: web::Data<Live>,
+    interval: Duration,
+}
+
+impl Scheduler {
+    pub fn new(store: web::Data<dyn Store>, live: web::Data<Live>) -> Self {
+        dotenv().ok();
+        let seconds = std::env::var("SCHEDULER_INTERVAL")
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(DEFAULT_INTERVAL);
+
+        Self {
+            store,
+            live,
+            interval: Duration::from_secs(seconds),
+        }
+    }
+}
+
+impl Actor for Scheduler {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.interval, |act, ctx| {
+            let store = act.store.clone();
+            let live = act.live.clone();
+
+            // a sweep that outlives the interval just overlaps the next one,
+            // `store::modify` keeps them from stepping on each other
+            ctx.spawn(
+                async move {
+                    if let Err(e) = sweep(&store, &live).await {
+                        log::error!("scheduler could not list topics: {}", e);
+                    }
+                    if let Err(e) = store.purge_expired().await {
+                        log::error!("scheduler could not purge expired keys: {}", e);
+                    }
+                }
+                .into_actor(act),
+            );
+        });
+    }
+}
+
+/// returns how many topics changed status. a topic that fails is
+/// logged and retried on the next sweep.
+async fn sweep(store: &web::Data<dyn Store>, live: &Live) -> Result<usize, StoreError> {
+    let now = Utc::now().timestamp();
+    let mut moved = 0;
+
+    for (topic_id, _) in store::get_list("topic", store).await? {
+        match sweep_topic(&topic_id, now, store, live).await {
+            Ok(true) => moved += 1,
+            Ok(false) => {}
+            Err(e) => log::error!("could not apply the schedule of topic:{}: {}", topic_id, e),
+        }
+    }
+
+    Ok(moved)
+}
+
+async fn sweep_topic(
+    topic_id: &str,
+    now: i64,
+    store: &web::Data<dyn Store>,
+    live: &Live,
+) -> Result<bool, StoreError> {
+    let topic: Topic = match store::get(topic_id, store).await? {
+        Some(x) => x,
+        None => return Ok(false),
+    };
+
+    if !topic.is_due(now) {
+        return Ok(false);
+    }
+
+    let mut changed = false;
+    let mut from = topic.status;
+
+    let topic = store::modify(topic_id, store, |topic: &mut Topic| {
+        from = topic.status;
+        changed = topic.apply_schedule(now);
+    })
+    .await?;
+
+    match (changed, topic) {
+        (true, Some(topic)) => {
+            log::info!("topic:{} is now {}", topic_id, topic.status.as_str());
+            status_moved(&topic, from, None, store, live).await;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::stream::EVENT_STREAM;
+    use crate::model::{DomainEvent, EventData, PartialTopic, Settable, Status};
+    use actix_web::rt::System;
+
+    #[test]
+    fn a_topic_past_both_times_opens_and_closes() {
+        System::new("test").block_on(async {
+            let store = store::memory();
+            let live = Live::default();
+            let now = Utc::now().timestamp();
+
+            let partial: PartialTopic = serde_json::from_value(serde_json::json!({
+                "title": "t",
+                "description": "d",
+                "opens_at": now - 20,
+                "closes_at": now - 10,
+            }))
+            .unwrap();
+            let topic: Topic = partial.into();
+            let topic_id = topic.id();
+            store::add(topic, &store).await.unwrap();
+            store.create_group(EVENT_STREAM, "test").await.unwrap();
+
+            assert!(sweep_topic(&topic_id, now, &store, &live).await.unwrap());
+            assert!(!sweep_topic(&topic_id, now, &store, &live).await.unwrap());
+
+            let topic: Topic = store::get(&topic_id, &store).await.unwrap().unwrap();
+            assert_eq!(topic.status, Status::Closed);
+
+            let statuses: Vec<Status> = store
+                .read_group(EVENT_STREAM, "test", "c", 10, false)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|(_, x)| match serde_json::from_slice::<DomainEvent>(&x).unwrap().data {
+                    EventData::StatusChanged { status, .. } => status,
+                    x => panic!("unexpected event {:?}", x),
+                })
+                .collect();
+            assert_eq!(statuses, vec![Status::Open, Status::Closed]);
+        });
+    }
+}
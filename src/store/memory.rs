@@ -0,0 +1,342 @@
+use crate::store::{Store, StoreError, STREAM_MAX_LEN};
+use async_trait::async_trait;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct Inner {
+    values: HashMap<String, (Vec<u8>, Option<Instant>)>,
+    sets: HashMap<String, BTreeSet<Vec<u8>>>,
+    streams: HashMap<String, Stream>,
+}
+
+#[derive(Default)]
+struct Stream {
+    last_id: u64,
+    entries: BTreeMap<u64, Vec<u8>>,
+    groups: HashMap<String, Group>,
+}
+
+#[derive(Default)]
+struct Group {
+    // the last entry handed out
+    delivered: u64,
+    // entry id to the consumer it was handed to
+    pending: BTreeMap<u64, String>,
+}
+
+/// in-process store, handy for development and tests without a redis server.
+/// clones share the same data, so one instance can be handed to every worker.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Inner>, StoreError> {
+        self.inner
+            .lock()
+            .map_err(|_| StoreError::Unavailable("memory store lock is poisoned".into()))
+    }
+}
+
+#[async_trait(?Send)]
+impl Store for MemoryStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        let mut inner = self.lock()?;
+
+        let expired = match inner.values.get(key) {
+            Some((_, Some(deadline))) => *deadline <= Instant::now(),
+            Some((value, None)) => return Ok(Some(value.to_owned())),
+            None => return Ok(None),
+        };
+
+        if expired {
+            inner.values.remove(key);
+            Ok(None)
+        } else {
+            Ok(inner.values.get(key).map(|(value, _)| value.to_owned()))
+        }
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<(), StoreError> {
+        let mut inner = self.lock()?;
+        inner.values.insert(key.to_string(), (value.to_vec(), None));
+        Ok(())
+    }
+
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        expected: &[u8],
+        value: &[u8],
+    ) -> Result<bool, StoreError> {
+        let mut inner = self.lock()?;
+
+        let matches = match inner.values.get(key) {
+            Some((current, Some(deadline))) => *deadline > Instant::now() && current == expected,
+            Some((current, None)) => current == expected,
+            None => false,
+        };
+
+        if matches {
+            inner.values.insert(key.to_string(), (value.to_vec(), None));
+        }
+
+        Ok(matches)
+    }
+
+    async fn set_expiring(
+        &self,
+        key: &str,
+        value: &[u8],
+        seconds: usize,
+    ) -> Result<(), StoreError> {
+        let deadline = Instant::now() + Duration::from_secs(seconds as u64);
+        let mut inner = self.lock()?;
+        inner
+            .values
+            .insert(key.to_string(), (value.to_vec(), Some(deadline)));
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool, StoreError> {
+        let mut inner = self.lock()?;
+        Ok(inner.values.remove(key).is_some())
+    }
+
+    async fn add_member(&self, set: &str, member: &[u8]) -> Result<(), StoreError> {
+        let mut inner = self.lock()?;
+        inner
+            .sets
+            .entry(set.to_string())
+            .or_default()
+            .insert(member.to_vec());
+        Ok(())
+    }
+
+    async fn remove_member(&self, set: &str, member: &[u8]) -> Result<(), StoreError> {
+        let mut inner = self.lock()?;
+        if let Some(members) = inner.sets.get_mut(set) {
+            members.remove(member);
+        }
+        Ok(())
+    }
+
+    async fn members(&self, set: &str) -> Result<Vec<Vec<u8>>, StoreError> {
+        let inner = self.lock()?;
+        Ok(inner
+            .sets
+            .get(set)
+            .map(|members| members.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn append(&self, stream: &str, entry: &[u8]) -> Result<String, StoreError> {
+        let mut inner = self.lock()?;
+        let stream = inner.streams.entry(stream.to_string()).or_default();
+
+        stream.last_id += 1;
+        stream.entries.insert(stream.last_id, entry.to_vec());
+
+        if stream.entries.len() > STREAM_MAX_LEN {
+            let oldest = stream.last_id - STREAM_MAX_LEN as u64;
+            stream.entries = stream.entries.split_off(&(oldest + 1));
+        }
+
+        Ok(stream.last_id.to_string())
+    }
+
+    async fn create_group(&self, stream: &str, group: &str) -> Result<(), StoreError> {
+        let mut inner = self.lock()?;
+        inner
+            .streams
+            .entry(stream.to_string())
+            .or_default()
+            .groups
+            .entry(group.to_string())
+            .or_default();
+        Ok(())
+    }
+
+    async fn read_group(
+        &self,
+        stream: &str,
+        group: &str,
+        consumer: &str,
+        count: usize,
+        pending: bool,
+    ) -> Result<Vec<(String, Vec<u8>)>, StoreError> {
+        let mut inner = self.lock()?;
+
+        let missing = || StoreError::Backend(format!("NOGROUP no group {} on stream {}", group, stream));
+        let stream = inner.streams.get_mut(stream).ok_or_else(missing)?;
+        let entries = &stream.entries;
+        let group = stream.groups.get_mut(group).ok_or_else(missing)?;
+
+        if pending {
+            return Ok(group
+                .pending
+                .iter()
+                .filter(|(_, owner)| *owner == consumer)
+                .filter_map(|(id, _)| entries.get(id).map(|x| (id.to_string(), x.to_owned())))
+                .take(count)
+                .collect());
+        }
+
+        let fresh: Vec<(u64, Vec<u8>)> = entries
+            .range(group.delivered + 1..)
+            .take(count)
+            .map(|(id, x)| (*id, x.to_owned()))
+            .collect();
+
+        for (id, _) in fresh.iter() {
+            group.delivered = *id;
+            group.pending.insert(*id, consumer.to_string());
+        }
+
+        Ok(fresh.into_iter().map(|(id, x)| (id.to_string(), x)).collect())
+    }
+
+    async fn ack(&self, stream: &str, group: &str, ids: &[String]) -> Result<usize, StoreError> {
+        let mut inner = self.lock()?;
+
+        let group = match inner
+            .streams
+            .get_mut(stream)
+            .and_then(|x| x.groups.get_mut(group))
+        {
+            Some(x) => x,
+            None => return Ok(0),
+        };
+
+        Ok(ids
+            .iter()
+            .filter_map(|id| id.parse::<u64>().ok())
+            .filter(|id| group.pending.remove(id).is_some())
+            .count())
+    }
+
+    async fn purge_expired(&self) -> Result<usize, StoreError> {
+        let mut inner = self.lock()?;
+        let now = Instant::now();
+        let before = inner.values.len();
+
+        inner
+            .values
+            .retain(|_, (_, deadline)| !matches!(deadline, Some(x) if *x <= now));
+
+        Ok(before - inner.values.len())
+    }
+
+    async fn flush(&self) -> Result<(), StoreError> {
+        let mut inner = self.lock()?;
+        inner.values.clear();
+        inner.sets.clear();
+        inner.streams.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn values() {
+        let store = MemoryStore::new();
+
+        assert_eq!(store.get("a").await.unwrap(), None);
+        store.set("a", b"1").await.unwrap();
+        assert_eq!(store.get("a").await.unwrap(), Some(b"1".to_vec()));
+
+        assert!(store.delete("a").await.unwrap());
+        assert!(!store.delete("a").await.unwrap());
+        assert_eq!(store.get("a").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn expiring_values() {
+        let store = MemoryStore::new();
+
+        store.set_expiring("gone", b"1", 0).await.unwrap();
+        store.set_expiring("kept", b"1", 60).await.unwrap();
+
+        assert_eq!(store.get("gone").await.unwrap(), None);
+        assert_eq!(store.get("kept").await.unwrap(), Some(b"1".to_vec()));
+        // an expired key can't be swapped either
+        assert!(!store.compare_and_set("gone", b"1", b"2").await.unwrap());
+
+        store.set_expiring("also gone", b"1", 0).await.unwrap();
+        assert_eq!(store.purge_expired().await.unwrap(), 1);
+        assert_eq!(store.lock().unwrap().values.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn compare_and_set() {
+        let store = MemoryStore::new();
+
+        assert!(!store.compare_and_set("a", b"1", b"2").await.unwrap());
+
+        store.set("a", b"1").await.unwrap();
+        assert!(!store.compare_and_set("a", b"0", b"2").await.unwrap());
+        assert_eq!(store.get("a").await.unwrap(), Some(b"1".to_vec()));
+
+        assert!(store.compare_and_set("a", b"1", b"2").await.unwrap());
+        assert_eq!(store.get("a").await.unwrap(), Some(b"2".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn sets() {
+        let store = MemoryStore::new();
+
+        store.add_member("s", b"x").await.unwrap();
+        store.add_member("s", b"x").await.unwrap();
+        store.add_member("s", b"y").await.unwrap();
+        assert_eq!(store.members("s").await.unwrap(), vec![b"x".to_vec(), b"y".to_vec()]);
+
+        store.remove_member("s", b"x").await.unwrap();
+        assert_eq!(store.members("s").await.unwrap(), vec![b"y".to_vec()]);
+        assert!(store.members("none").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn clones_share_data_and_flush_wipes_it() {
+        let store = MemoryStore::new();
+        let other = store.clone();
+
+        store.set("a", b"1").await.unwrap();
+        store.add_member("s", b"x").await.unwrap();
+        assert_eq!(other.get("a").await.unwrap(), Some(b"1".to_vec()));
+
+        other.flush().await.unwrap();
+        assert_eq!(store.get("a").await.unwrap(), None);
+        assert!(store.members("s").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn stream_groups() {
+        crate::store::check_stream_groups(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn streams_are_trimmed() {
+        let store = MemoryStore::new();
+        store.create_group("s", "g").await.unwrap();
+        store.append("s", b"first").await.unwrap();
+        store.read_group("s", "g", "c", 1, false).await.unwrap();
+
+        for _ in 0..STREAM_MAX_LEN {
+            store.append("s", b"x").await.unwrap();
+        }
+
+        assert_eq!(store.lock().unwrap().streams["s"].entries.len(), STREAM_MAX_LEN);
+        // a pending entry that was trimmed isn't handed out again
+        assert!(store.read_group("s", "g", "c", 10, true).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,404 @@
+mod memory;
+mod redis;
+mod sqlite;
+
+pub use self::memory::MemoryStore;
+pub use self::redis::RedisStore;
+pub use self::sqlite::SqliteStore;
+
+use crate::model::Settable;
+use actix_web::web;
+use async_trait::async_trait;
+use futures::future::{join, join_all};
+use serde::de::DeserializeOwned;
+
+/// how many times `modify` re-reads an object that changed under it
+const MODIFY_ATTEMPTS: usize = 5;
+
+/// about how many entries every backend keeps of a stream, the oldest
+/// are trimmed beyond that
+pub(crate) const STREAM_MAX_LEN: usize = 100_000;
+
+#[derive(Debug)]
+pub enum StoreError {
+    /// the backend could not be reached or the message got lost
+    Unavailable(String),
+    /// the backend answered, but not with what we asked for
+    Backend(String),
+    /// data in the store is not in the shape we expected
+    Corrupt(String),
+    /// someone else kept writing the same key
+    Conflict(String),
+}
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::Unavailable(x) => write!(f, "store unavailable: {}", x),
+            StoreError::Backend(x) => write!(f, "store error: {}", x),
+            StoreError::Corrupt(x) => write!(f, "corrupt data in store: {}", x),
+            StoreError::Conflict(x) => write!(f, "conflicting update: {}", x),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+/// the raw key-value operations the api needs from a backend.
+/// keys follow the `{prefix}:{id}` convention of `Settable::domain`,
+/// and each prefix has a set named `{prefix}s` listing its items.
+#[async_trait(?Send)]
+pub trait Store {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StoreError>;
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<(), StoreError>;
+
+    /// writes `value` only if the key still holds `expected`.
+    /// returns false, without writing, if it doesn't
+    async fn compare_and_set(&self, key: &str, expected: &[u8], value: &[u8])
+        -> Result<bool, StoreError>;
+
+    /// same as `set`, but the key disappears after `seconds`
+    async fn set_expiring(&self, key: &str, value: &[u8], seconds: usize)
+        -> Result<(), StoreError>;
+
+    /// returns true if the key existed
+    async fn delete(&self, key: &str) -> Result<bool, StoreError>;
+
+    async fn add_member(&self, set: &str, member: &[u8]) -> Result<(), StoreError>;
+
+    async fn remove_member(&self, set: &str, member: &[u8]) -> Result<(), StoreError>;
+
+    async fn members(&self, set: &str) -> Result<Vec<Vec<u8>>, StoreError>;
+
+    /// adds `entry` to the end of an append-only stream and returns its
+    /// id. ids only grow, but are otherwise opaque
+    async fn append(&self, stream: &str, entry: &[u8]) -> Result<String, StoreError>;
+
+    /// a consumer group reads every entry of the stream once, spread over
+    /// its consumers. a new group starts at the beginning, creating one
+    /// that exists does nothing
+    async fn create_group(&self, stream: &str, group: &str) -> Result<(), StoreError>;
+
+    /// up to `count` entries the group hasn't handed out yet, now pending
+    /// for `consumer` until acked. with `pending`, the entries already
+    /// handed to `consumer` and not acked, to pick up after a crash
+    async fn read_group(
+        &self,
+        stream: &str,
+        group: &str,
+        consumer: &str,
+        count: usize,
+        pending: bool,
+    ) -> Result<Vec<(String, Vec<u8>)>, StoreError>;
+
+    /// returns how many of `ids` were pending
+    async fn ack(&self, stream: &str, group: &str, ids: &[String]) -> Result<usize, StoreError>;
+
+    /// drops the keys that have expired and returns how many. expired keys
+    /// are never read, this only gives their space back
+    async fn purge_expired(&self) -> Result<usize, StoreError>;
+
+    /// wipes everything, used by the nuclear endpoint
+    async fn flush(&self) -> Result<(), StoreError>;
+}
+
+pub async fn add(obj: impl Settable, store: &web::Data<dyn Store>) -> Result<(), StoreError> {
+    let plural_domain = format!("{}s", obj.prefix());
+
+    let (domain, json, list_item) = (obj.domain(), obj.json(), obj.list_item());
+
+    let add = store.set(&domain, json.as_bytes());
+    let list = store.add_member(&plural_domain, list_item.as_bytes());
+
+    let (add, list) = join(add, list).await;
+
+    add.and(list)
+}
+
+/// read-modify-write of a single object. `f` runs on a fresh copy each
+/// attempt, so it may be called more than once. returns `None` if the
+/// object doesn't exist and `StoreError::Conflict` if it kept changing.
+pub async fn modify<T, F>(
+    id: &str,
+    store: &web::Data<dyn Store>,
+    mut f: F,
+) -> Result<Option<T>, StoreError>
+where
+    T: Settable + DeserializeOwned,
+    F: FnMut(&mut T),
+{
+    let domain = format!("{}:{}", T::domain_prefix(), id);
+
+    for _ in 0..MODIFY_ATTEMPTS {
+        let current = match store.get(&domain).await? {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        let mut obj: T = parse(&current, &domain)?;
+
+        f(&mut obj);
+
+        let json = obj.json();
+
+        if json.as_bytes() == current.as_slice()
+            || store.compare_and_set(&domain, &current, json.as_bytes()).await?
+        {
+            return Ok(Some(obj));
+        }
+    }
+
+    Err(StoreError::Conflict(format!(
+        "{} kept changing, gave up after {} attempts",
+        domain, MODIFY_ATTEMPTS
+    )))
+}
+
+// to delete the object, you need to get it first
+// in order to remove it from the SET
+pub async fn delete(obj: impl Settable, store: &web::Data<dyn Store>) -> Result<bool, StoreError> {
+    let plural_domain = format!("{}s", obj.prefix());
+
+    let (domain, list_item) = (obj.domain(), obj.list_item());
+
+    let del = store.delete(&domain);
+    let pop = store.remove_member(&plural_domain, list_item.as_bytes());
+
+    let (del, pop) = join(del, pop).await;
+
+    pop?;
+    del
+}
+
+pub async fn get_slice(
+    id: &str,
+    domain_prefix: &str,
+    store: &web::Data<dyn Store>,
+) -> Result<Option<Vec<u8>>, StoreError> {
+    let domain = format!("{}:{}", domain_prefix, id);
+    store.get(&domain).await
+}
+
+fn parse<T: DeserializeOwned>(slice: &[u8], domain: &str) -> Result<T, StoreError> {
+    serde_json::from_slice(slice)
+        .map_err(|_| StoreError::Corrupt(format!("{} is not deserializable", domain)))
+}
+
+pub async fn get<T: Settable + DeserializeOwned>(
+    id: &str,
+    store: &web::Data<dyn Store>,
+) -> Result<Option<T>, StoreError> {
+    let domain = format!("{}:{}", T::domain_prefix(), id);
+
+    match store.get(&domain).await? {
+        Some(x) => parse(&x, &domain).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// pairs of (id, object), missing ids are skipped
+pub async fn get_all<T: DeserializeOwned>(
+    ids: &[String],
+    domain_prefix: &str,
+    store: &web::Data<dyn Store>,
+) -> Result<Vec<(String, T)>, StoreError> {
+    let get_list = join_all(ids.iter().map(|id| get_slice(id, domain_prefix, store))).await;
+
+    let mut objs = Vec::new();
+
+    for (id, obj) in ids.iter().zip(get_list) {
+        if let Some(x) = obj? {
+            let domain = format!("{}:{}", domain_prefix, id);
+            objs.push((id.to_string(), parse(&x, &domain)?));
+        }
+    }
+
+    Ok(objs)
+}
+
+pub async fn get_list(
+    domain: &str,
+    store: &web::Data<dyn Store>,
+) -> Result<Vec<(String, String)>, StoreError> {
+    let plural = format!("{}s", domain);
+
+    let members = store.members(&plural).await?;
+
+    members
+        .iter()
+        .map(|x| {
+            serde_json::from_slice(x).map_err(|_| {
+                StoreError::Corrupt(format!("list item in '{}' is not (String, String)", plural))
+            })
+        })
+        .collect()
+}
+
+/// an empty in-memory store, handed around the way the server hands its own
+#[cfg(test)]
+pub fn memory() -> web::Data<dyn Store> {
+    let store: std::sync::Arc<dyn Store> = std::sync::Arc::new(MemoryStore::new());
+    web::Data::from(store)
+}
+
+/// how consumer groups behave on every backend, run by each backend's tests
+#[cfg(test)]
+pub async fn check_stream_groups(store: &dyn Store) {
+    let ids = |entries: Vec<(String, Vec<u8>)>| -> Vec<Vec<u8>> {
+        entries.into_iter().map(|(_, x)| x).collect()
+    };
+
+    let missing = store.read_group("s", "g", "c1", 10, false).await;
+    assert!(matches!(missing, Err(StoreError::Backend(e)) if e.starts_with("NOGROUP")));
+
+    // a group made after the entries still gets them all
+    let first = store.append("s", b"1").await.unwrap();
+    store.create_group("s", "g").await.unwrap();
+    store.create_group("s", "g").await.unwrap();
+    store.append("s", b"2").await.unwrap();
+    store.append("s", b"3").await.unwrap();
+
+    let c1 = store.read_group("s", "g", "c1", 2, false).await.unwrap();
+    assert_eq!(ids(c1.clone()), vec![b"1".to_vec(), b"2".to_vec()]);
+    let c2 = store.read_group("s", "g", "c2", 10, false).await.unwrap();
+    assert_eq!(ids(c2), vec![b"3".to_vec()]);
+    assert!(store.read_group("s", "g", "c1", 10, false).await.unwrap().is_empty());
+
+    // every group sees every entry
+    store.create_group("s", "other").await.unwrap();
+    assert_eq!(store.read_group("s", "other", "c1", 10, false).await.unwrap().len(), 3);
+
+    // pending until acked, bad and unknown ids are left alone
+    assert_eq!(store.read_group("s", "g", "c1", 10, true).await.unwrap(), c1);
+    let acked = store
+        .ack("s", "g", &[first.to_owned(), first, "x".to_string(), "999".to_string()])
+        .await
+        .unwrap();
+    assert_eq!(acked, 1);
+    assert_eq!(ids(store.read_group("s", "g", "c1", 10, true).await.unwrap()), vec![b"2".to_vec()]);
+    assert_eq!(store.ack("nothing", "g", &["1".to_string()]).await.unwrap(), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ApiError;
+    use crate::model::User;
+    use actix_web::ResponseError;
+    use futures::executor::block_on;
+
+    fn user() -> User {
+        User::new("tester".into(), "tester@example.com".into())
+    }
+
+    #[tokio::test]
+    async fn add_get_and_delete() {
+        let store = memory();
+        let user = user();
+        let id = user.id();
+
+        add(user.clone(), &store).await.unwrap();
+
+        let got: User = get(&id, &store).await.unwrap().unwrap();
+        assert_eq!(got.nickname, "tester");
+        assert_eq!(get_list("user", &store).await.unwrap(), vec![(id.to_owned(), "tester".to_string())]);
+
+        assert!(delete(user, &store).await.unwrap());
+        assert!(get::<User>(&id, &store).await.unwrap().is_none());
+        assert!(get_list("user", &store).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn get_all_skips_missing() {
+        let store = memory();
+        let user = user();
+        add(user.clone(), &store).await.unwrap();
+
+        let ids = vec![user.id(), "nobody".to_string()];
+        let found = get_all::<User>(&ids, "user", &store).await.unwrap();
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, user.id());
+    }
+
+    #[tokio::test]
+    async fn modify_writes_the_change() {
+        let store = memory();
+        let user = user();
+        add(user.clone(), &store).await.unwrap();
+
+        let changed = modify(&user.id(), &store, |x: &mut User| x.is_verified = true)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(changed.is_verified);
+
+        let stored: User = get(&user.id(), &store).await.unwrap().unwrap();
+        assert!(stored.is_verified);
+    }
+
+    #[tokio::test]
+    async fn modify_missing_is_none() {
+        let store = memory();
+
+        let modified = modify("nobody", &store, |x: &mut User| x.is_verified = true).await;
+
+        assert!(modified.unwrap().is_none());
+    }
+
+    // the closure writes behind modify's back, like another worker would
+    fn interfere(store: &web::Data<dyn Store>, user: &User, nickname: &str) {
+        let mut other = user.clone();
+        other.nickname = nickname.to_string();
+        block_on(store.set(&other.domain(), other.json().as_bytes())).unwrap();
+    }
+
+    #[tokio::test]
+    async fn modify_retries_after_a_conflict() {
+        let store = memory();
+        let user = user();
+        add(user.clone(), &store).await.unwrap();
+
+        let mut calls = 0;
+        let changed = modify(&user.id(), &store, |x: &mut User| {
+            calls += 1;
+            if calls == 1 {
+                interfere(&store, &user, "someone else");
+            }
+            x.is_verified = true;
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(calls, 2);
+        // the retry started from what the other writer left
+        assert_eq!(changed.nickname, "someone else");
+        assert!(changed.is_verified);
+    }
+
+    #[tokio::test]
+    async fn modify_gives_up_with_a_409() {
+        let store = memory();
+        let user = user();
+        add(user.clone(), &store).await.unwrap();
+
+        let mut calls = 0;
+        let modified = modify(&user.id(), &store, |x: &mut User| {
+            calls += 1;
+            interfere(&store, &user, &format!("writer {}", calls));
+            x.is_verified = true;
+        })
+        .await;
+
+        assert_eq!(calls, MODIFY_ATTEMPTS);
+
+        let error = match modified {
+            Err(e @ StoreError::Conflict(_)) => ApiError::from(e),
+            x => panic!("expected a conflict, got {:?}", x),
+        };
+        assert_eq!(error.status_code().as_u16(), 409);
+        assert_eq!(error.code(), "conflict");
+    }
+}
@@ -0,0 +1,235 @@
+use crate::store::{Store, StoreError, STREAM_MAX_LEN};
+use actix::Addr;
+use actix_redis::{Command, RedisActor};
+use async_trait::async_trait;
+use redis_async::{resp::RespValue as Value, resp_array};
+
+// SET only if the key still holds what we read, in one round trip.
+// WATCH/MULTI would need a connection of our own, the actor shares one.
+const COMPARE_AND_SET: &str = "
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    redis.call('SET', KEYS[1], ARGV[2])
+    return 1
+end
+return 0
+";
+
+// streams are trimmed to about this many entries, consumers are expected
+// to keep up long before that
+
+#[derive(Clone)]
+pub struct RedisStore {
+    addr: Addr<RedisActor>,
+}
+
+impl RedisStore {
+    pub fn start(address: &str) -> Self {
+        Self {
+            addr: RedisActor::start(address),
+        }
+    }
+
+    async fn send(&self, cmd: Value) -> Result<Value, StoreError> {
+        match self.addr.send(Command(cmd)).await {
+            Ok(Ok(Value::Error(e))) => Err(StoreError::Backend(e)),
+            Ok(Ok(x)) => Ok(x),
+            Ok(Err(e)) => Err(StoreError::Unavailable(e.to_string())),
+            Err(e) => Err(StoreError::Unavailable(e.to_string())),
+        }
+    }
+
+    async fn send_ok(&self, cmd: Value) -> Result<(), StoreError> {
+        match self.send(cmd).await? {
+            Value::SimpleString(x) if x == "OK" => Ok(()),
+            x => Err(StoreError::Backend(format!("unexpected reply {:?}", x))),
+        }
+    }
+}
+
+// XREADGROUP answers [[stream, [[id, [field, value, ...]], ...]]], or nil
+// if there is nothing. entries deleted while pending come without fields
+fn stream_entries(reply: Value) -> Result<Vec<(String, Vec<u8>)>, StoreError> {
+    let unexpected = |x: &Value| StoreError::Backend(format!("unexpected reply {:?}", x));
+
+    let streams = match reply {
+        Value::Nil => return Ok(Vec::new()),
+        Value::Array(x) => x,
+        x => return Err(unexpected(&x)),
+    };
+
+    let mut entries = Vec::new();
+
+    for stream in streams {
+        let list = match stream {
+            Value::Array(mut x) if x.len() == 2 => x.pop().unwrap_or(Value::Nil),
+            x => return Err(unexpected(&x)),
+        };
+
+        let list = match list {
+            Value::Array(x) => x,
+            x => return Err(unexpected(&x)),
+        };
+
+        for entry in list {
+            let (id, fields) = match entry {
+                Value::Array(mut x) if x.len() == 2 => {
+                    let fields = x.pop().unwrap_or(Value::Nil);
+                    (x.pop().unwrap_or(Value::Nil), fields)
+                }
+                x => return Err(unexpected(&x)),
+            };
+
+            let id = match id {
+                Value::BulkString(x) => String::from_utf8_lossy(&x).into_owned(),
+                x => return Err(unexpected(&x)),
+            };
+
+            // we only ever write the one field
+            if let Value::Array(mut fields) = fields {
+                if let Some(Value::BulkString(data)) = fields.pop() {
+                    entries.push((id, data));
+                }
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+#[async_trait(?Send)]
+impl Store for RedisStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        match self.send(resp_array!["GET", key]).await? {
+            Value::BulkString(x) => Ok(Some(x)),
+            Value::Nil => Ok(None),
+            x => Err(StoreError::Backend(format!("unexpected reply {:?}", x))),
+        }
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<(), StoreError> {
+        self.send_ok(resp_array!["SET", key, value]).await
+    }
+
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        expected: &[u8],
+        value: &[u8],
+    ) -> Result<bool, StoreError> {
+        match self
+            .send(resp_array!["EVAL", COMPARE_AND_SET, "1", key, expected, value])
+            .await?
+        {
+            Value::Integer(x) => Ok(x == 1),
+            x => Err(StoreError::Backend(format!("unexpected reply {:?}", x))),
+        }
+    }
+
+    async fn set_expiring(
+        &self,
+        key: &str,
+        value: &[u8],
+        seconds: usize,
+    ) -> Result<(), StoreError> {
+        self.send_ok(resp_array!["SET", key, value, "EX", seconds.to_string()])
+            .await
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool, StoreError> {
+        match self.send(resp_array!["DEL", key]).await? {
+            Value::Integer(x) => Ok(x == 1),
+            x => Err(StoreError::Backend(format!("unexpected reply {:?}", x))),
+        }
+    }
+
+    async fn add_member(&self, set: &str, member: &[u8]) -> Result<(), StoreError> {
+        self.send(resp_array!["SADD", set, member]).await.map(|_| ())
+    }
+
+    async fn remove_member(&self, set: &str, member: &[u8]) -> Result<(), StoreError> {
+        self.send(resp_array!["SREM", set, member]).await.map(|_| ())
+    }
+
+    async fn members(&self, set: &str) -> Result<Vec<Vec<u8>>, StoreError> {
+        match self.send(resp_array!["SMEMBERS", set]).await? {
+            Value::Array(x) => Ok(x
+                .into_iter()
+                .filter_map(|e| match e {
+                    Value::BulkString(x) => Some(x),
+                    _ => None,
+                })
+                .collect()),
+            x => Err(StoreError::Backend(format!("unexpected reply {:?}", x))),
+        }
+    }
+
+    async fn append(&self, stream: &str, entry: &[u8]) -> Result<String, StoreError> {
+        match self
+            .send(resp_array!["XADD", stream, "MAXLEN", "~", STREAM_MAX_LEN.to_string(), "*", "data", entry])
+            .await?
+        {
+            Value::BulkString(x) => Ok(String::from_utf8_lossy(&x).into_owned()),
+            x => Err(StoreError::Backend(format!("unexpected reply {:?}", x))),
+        }
+    }
+
+    async fn create_group(&self, stream: &str, group: &str) -> Result<(), StoreError> {
+        match self
+            .send_ok(resp_array!["XGROUP", "CREATE", stream, group, "0", "MKSTREAM"])
+            .await
+        {
+            Err(StoreError::Backend(e)) if e.starts_with("BUSYGROUP") => Ok(()),
+            x => x,
+        }
+    }
+
+    async fn read_group(
+        &self,
+        stream: &str,
+        group: &str,
+        consumer: &str,
+        count: usize,
+        pending: bool,
+    ) -> Result<Vec<(String, Vec<u8>)>, StoreError> {
+        let from = if pending { "0" } else { ">" };
+
+        let reply = self
+            .send(resp_array![
+                "XREADGROUP",
+                "GROUP",
+                group,
+                consumer,
+                "COUNT",
+                count.to_string(),
+                "STREAMS",
+                stream,
+                from
+            ])
+            .await?;
+
+        stream_entries(reply)
+    }
+
+    async fn ack(&self, stream: &str, group: &str, ids: &[String]) -> Result<usize, StoreError> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+
+        match self
+            .send(resp_array!["XACK", stream, group].append(ids.iter()))
+            .await?
+        {
+            Value::Integer(x) => Ok(x as usize),
+            x => Err(StoreError::Backend(format!("unexpected reply {:?}", x))),
+        }
+    }
+
+    // redis expires keys on its own
+    async fn purge_expired(&self) -> Result<usize, StoreError> {
+        Ok(0)
+    }
+
+    async fn flush(&self) -> Result<(), StoreError> {
+        self.send_ok(resp_array!["FLUSHALL"]).await
+    }
+}
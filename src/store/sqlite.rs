@@ -0,0 +1,724 @@
+use crate::model::Topic;
+use crate::store::{Store, StoreError, STREAM_MAX_LEN};
+use actix_web::{error::BlockingError, web};
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension, Transaction, NO_PARAMS};
+use std::sync::{Arc, Mutex};
+
+// appends between two trims down to `STREAM_MAX_LEN`
+const STREAM_TRIM_EVERY: i64 = 100;
+
+// every entry is one schema version, applied in order and tracked
+// with `PRAGMA user_version`. never edit an entry, append a new one.
+const MIGRATIONS: &[&str] = &[
+    // 1: initial schema
+    "CREATE TABLE users (
+        id TEXT PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE plans (
+        id TEXT PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE topics (
+        id TEXT PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE topic_plans (
+        topic_id TEXT NOT NULL REFERENCES topics(id) ON DELETE CASCADE,
+        plan_id TEXT NOT NULL REFERENCES plans(id) ON DELETE CASCADE,
+        PRIMARY KEY (topic_id, plan_id)
+    );
+    CREATE TABLE topic_voters (
+        topic_id TEXT NOT NULL REFERENCES topics(id) ON DELETE CASCADE,
+        user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        PRIMARY KEY (topic_id, user_id)
+    );
+    CREATE TABLE settings (
+        hash TEXT PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE access_tokens (
+        token TEXT PRIMARY KEY,
+        user_id TEXT NOT NULL,
+        expires_at INTEGER
+    );
+    CREATE INDEX access_tokens_user_id ON access_tokens(user_id);
+    CREATE TRIGGER users_delete_access_tokens AFTER DELETE ON users
+    BEGIN
+        DELETE FROM access_tokens WHERE user_id = OLD.id;
+    END;
+    CREATE TABLE kv (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL,
+        expires_at INTEGER
+    );
+    CREATE TABLE members (
+        name TEXT NOT NULL,
+        member TEXT NOT NULL,
+        PRIMARY KEY (name, member)
+    );",
+    // 2: streams and their consumer groups
+    "CREATE TABLE stream_entries (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        stream TEXT NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX stream_entries_stream ON stream_entries(stream, id);
+    CREATE TABLE stream_groups (
+        stream TEXT NOT NULL,
+        name TEXT NOT NULL,
+        delivered INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (stream, name)
+    );
+    CREATE TABLE stream_pending (
+        stream TEXT NOT NULL,
+        group_name TEXT NOT NULL,
+        entry_id INTEGER NOT NULL,
+        consumer TEXT NOT NULL,
+        PRIMARY KEY (stream, group_name, entry_id),
+        FOREIGN KEY (stream, group_name) REFERENCES stream_groups(stream, name) ON DELETE CASCADE
+    );",
+];
+
+/// where a key lives in the schema
+struct Route {
+    table: &'static str,
+    key: &'static str,
+    value: &'static str,
+    expires: bool,
+}
+
+const USERS: Route = Route { table: "users", key: "id", value: "data", expires: false };
+const PLANS: Route = Route { table: "plans", key: "id", value: "data", expires: false };
+const TOPICS: Route = Route { table: "topics", key: "id", value: "data", expires: false };
+const SETTINGS: Route = Route { table: "settings", key: "hash", value: "data", expires: false };
+const ACCESS_TOKENS: Route = Route { table: "access_tokens", key: "token", value: "user_id", expires: true };
+const KV: Route = Route { table: "kv", key: "key", value: "value", expires: true };
+
+/// splits `{prefix}:{id}` into its table and the id within it.
+/// anything we don't have a table for lands in `kv` under the full key.
+fn route(key: &str) -> (&'static Route, &str) {
+    let mut split = key.splitn(2, ':');
+
+    match (split.next(), split.next()) {
+        (Some("user"), Some(id)) => (&USERS, id),
+        (Some("plan"), Some(id)) => (&PLANS, id),
+        (Some("topic"), Some(id)) => (&TOPICS, id),
+        (Some("setting"), Some(id)) => (&SETTINGS, id),
+        (Some("access_token"), Some(id)) => (&ACCESS_TOKENS, id),
+        _ => (&KV, key),
+    }
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+fn text(value: &[u8]) -> Result<String, StoreError> {
+    String::from_utf8(value.to_vec())
+        .map_err(|_| StoreError::Backend("sqlite store only keeps utf-8 values".into()))
+}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::Backend(e.to_string())
+    }
+}
+
+/// relational store backed by a single sqlite file. topics are linked to
+/// their plans and voters, the links go when the plan or user does.
+#[derive(Clone)]
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &str) -> Result<Self, StoreError> {
+        let mut conn = Connection::open(path)?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        migrate(&mut conn)?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    async fn run<F, T>(&self, f: F) -> Result<T, StoreError>
+    where
+        F: FnOnce(&mut Connection) -> Result<T, StoreError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let conn = self.conn.clone();
+
+        web::block(move || {
+            let mut conn = conn
+                .lock()
+                .map_err(|_| StoreError::Unavailable("sqlite connection lock is poisoned".into()))?;
+            f(&mut conn)
+        })
+        .await
+        .map_err(|e| match e {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => StoreError::Unavailable("thread pool is gone".into()),
+        })
+    }
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), StoreError> {
+    let version: i64 = conn.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))?;
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.execute_batch(&format!("PRAGMA user_version = {}", i + 1))?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+fn fetch(conn: &Connection, route: &Route, id: &str) -> Result<Option<String>, StoreError> {
+    let value = if route.expires {
+        let sql = format!(
+            "SELECT {v} FROM {t} WHERE {k} = ?1 AND (expires_at IS NULL OR expires_at > ?2)",
+            t = route.table,
+            k = route.key,
+            v = route.value
+        );
+        conn.query_row(&sql, params![id, now()], |row| row.get(0))
+            .optional()?
+    } else {
+        let sql = format!(
+            "SELECT {v} FROM {t} WHERE {k} = ?1",
+            t = route.table,
+            k = route.key,
+            v = route.value
+        );
+        conn.query_row(&sql, params![id], |row| row.get(0))
+            .optional()?
+    };
+
+    Ok(value)
+}
+
+fn put(
+    conn: &Connection,
+    route: &Route,
+    id: &str,
+    value: &str,
+    expires_at: Option<i64>,
+) -> Result<(), StoreError> {
+    if route.expires {
+        let sql = format!(
+            "INSERT INTO {t} ({k}, {v}, expires_at) VALUES (?1, ?2, ?3)
+            ON CONFLICT({k}) DO UPDATE SET {v} = excluded.{v}, expires_at = excluded.expires_at",
+            t = route.table,
+            k = route.key,
+            v = route.value
+        );
+        conn.execute(&sql, params![id, value, expires_at])?;
+    } else if expires_at.is_some() {
+        return Err(StoreError::Backend(format!(
+            "entries in '{}' can't expire",
+            route.table
+        )));
+    } else {
+        let sql = format!(
+            "INSERT INTO {t} ({k}, {v}) VALUES (?1, ?2)
+            ON CONFLICT({k}) DO UPDATE SET {v} = excluded.{v}",
+            t = route.table,
+            k = route.key,
+            v = route.value
+        );
+        conn.execute(&sql, params![id, value])?;
+    }
+
+    Ok(())
+}
+
+// the topic row and its links to plans and voters are written together.
+// the links are to the plans and users that exist: deleting one drops its
+// links, but it stays in the settings it was in, as on the other backends,
+// so the topic has to stay writable with it.
+fn put_topic(tx: &Transaction, id: &str, value: &str) -> Result<(), StoreError> {
+    let topic: Topic = serde_json::from_str(value)
+        .map_err(|_| StoreError::Corrupt(format!("topic:{} is not a Topic", id)))?;
+
+    tx.execute(
+        "INSERT INTO topics (id, data) VALUES (?1, ?2)
+        ON CONFLICT(id) DO UPDATE SET data = excluded.data",
+        params![id, value],
+    )?;
+    tx.execute("DELETE FROM topic_plans WHERE topic_id = ?1", params![id])?;
+    tx.execute("DELETE FROM topic_voters WHERE topic_id = ?1", params![id])?;
+
+    for plan_id in topic.plan_ids() {
+        tx.execute(
+            "INSERT INTO topic_plans (topic_id, plan_id) SELECT ?1, id FROM plans WHERE id = ?2",
+            params![id, plan_id],
+        )?;
+    }
+
+    for user_id in topic.voter_ids() {
+        tx.execute(
+            "INSERT INTO topic_voters (topic_id, user_id) SELECT ?1, id FROM users WHERE id = ?2",
+            params![id, user_id],
+        )?;
+    }
+
+    Ok(())
+}
+
+fn write(conn: &mut Connection, key: &str, value: &str) -> Result<(), StoreError> {
+    let tx = conn.transaction()?;
+
+    match route(key) {
+        (route, id) if route.table == TOPICS.table => put_topic(&tx, id, value)?,
+        (route, id) => put(&tx, route, id, value, None)?,
+    }
+
+    tx.commit()?;
+    Ok(())
+}
+
+// (id, data) of stream entries
+fn stream_rows(
+    conn: &Connection,
+    sql: &str,
+    params: &[&dyn rusqlite::ToSql],
+) -> Result<Vec<(i64, String)>, StoreError> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map(params, |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+    let mut entries = Vec::new();
+    for row in rows {
+        entries.push(row?);
+    }
+    Ok(entries)
+}
+
+#[async_trait(?Send)]
+impl Store for SqliteStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        let key = key.to_string();
+
+        self.run(move |conn| {
+            let (route, id) = route(&key);
+            Ok(fetch(conn, route, id)?.map(String::into_bytes))
+        })
+        .await
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<(), StoreError> {
+        let (key, value) = (key.to_string(), text(value)?);
+
+        self.run(move |conn| write(conn, &key, &value)).await
+    }
+
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        expected: &[u8],
+        value: &[u8],
+    ) -> Result<bool, StoreError> {
+        let (key, expected, value) = (key.to_string(), text(expected)?, text(value)?);
+
+        // the connection is behind a mutex, nobody can write between
+        // the read and the write
+        self.run(move |conn| {
+            let (route, id) = route(&key);
+
+            match fetch(conn, route, id)? {
+                Some(current) if current == expected => {
+                    write(conn, &key, &value)?;
+                    Ok(true)
+                }
+                _ => Ok(false),
+            }
+        })
+        .await
+    }
+
+    async fn set_expiring(
+        &self,
+        key: &str,
+        value: &[u8],
+        seconds: usize,
+    ) -> Result<(), StoreError> {
+        let (key, value) = (key.to_string(), text(value)?);
+        let expires_at = now() + seconds as i64;
+
+        self.run(move |conn| {
+            let (route, id) = route(&key);
+            put(conn, route, id, &value, Some(expires_at))
+        })
+        .await
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool, StoreError> {
+        let key = key.to_string();
+
+        self.run(move |conn| {
+            let (route, id) = route(&key);
+            let sql = format!("DELETE FROM {} WHERE {} = ?1", route.table, route.key);
+            Ok(conn.execute(&sql, params![id])? == 1)
+        })
+        .await
+    }
+
+    async fn add_member(&self, set: &str, member: &[u8]) -> Result<(), StoreError> {
+        let (set, member) = (set.to_string(), text(member)?);
+
+        self.run(move |conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO members (name, member) VALUES (?1, ?2)",
+                params![set, member],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn remove_member(&self, set: &str, member: &[u8]) -> Result<(), StoreError> {
+        let (set, member) = (set.to_string(), text(member)?);
+
+        self.run(move |conn| {
+            conn.execute(
+                "DELETE FROM members WHERE name = ?1 AND member = ?2",
+                params![set, member],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn members(&self, set: &str) -> Result<Vec<Vec<u8>>, StoreError> {
+        let set = set.to_string();
+
+        self.run(move |conn| {
+            let mut stmt = conn.prepare("SELECT member FROM members WHERE name = ?1")?;
+            let rows = stmt.query_map(params![set], |row| row.get::<_, String>(0))?;
+
+            let mut members = Vec::new();
+            for row in rows {
+                members.push(row?.into_bytes());
+            }
+            Ok(members)
+        })
+        .await
+    }
+
+    async fn append(&self, stream: &str, entry: &[u8]) -> Result<String, StoreError> {
+        let (stream, entry) = (stream.to_string(), text(entry)?);
+
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "INSERT INTO stream_entries (stream, data) VALUES (?1, ?2)",
+                params![stream, entry],
+            )?;
+            let id = tx.last_insert_rowid();
+
+            // like redis' `MAXLEN ~`, trimming now and then is close enough
+            if id % STREAM_TRIM_EVERY == 0 {
+                tx.execute(
+                    "DELETE FROM stream_entries WHERE stream = ?1 AND id <= (
+                        SELECT id FROM stream_entries WHERE stream = ?1
+                        ORDER BY id DESC LIMIT 1 OFFSET ?2
+                    )",
+                    params![stream, STREAM_MAX_LEN as i64],
+                )?;
+                // what was trimmed can't be handed out again, nor acked
+                tx.execute(
+                    "DELETE FROM stream_pending WHERE stream = ?1 AND entry_id < (
+                        SELECT MIN(id) FROM stream_entries WHERE stream = ?1
+                    )",
+                    params![stream],
+                )?;
+            }
+
+            tx.commit()?;
+            Ok(id.to_string())
+        })
+        .await
+    }
+
+    async fn create_group(&self, stream: &str, group: &str) -> Result<(), StoreError> {
+        let (stream, group) = (stream.to_string(), group.to_string());
+
+        self.run(move |conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO stream_groups (stream, name) VALUES (?1, ?2)",
+                params![stream, group],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn read_group(
+        &self,
+        stream: &str,
+        group: &str,
+        consumer: &str,
+        count: usize,
+        pending: bool,
+    ) -> Result<Vec<(String, Vec<u8>)>, StoreError> {
+        let (stream, group, consumer) = (stream.to_string(), group.to_string(), consumer.to_string());
+        let count = count as i64;
+
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+
+            let delivered: i64 = tx
+                .query_row(
+                    "SELECT delivered FROM stream_groups WHERE stream = ?1 AND name = ?2",
+                    params![stream, group],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .ok_or_else(|| {
+                    StoreError::Backend(format!("NOGROUP no group {} on stream {}", group, stream))
+                })?;
+
+            let entries = match pending {
+                true => stream_rows(
+                    &tx,
+                    "SELECT e.id, e.data FROM stream_pending p
+                    JOIN stream_entries e ON e.id = p.entry_id
+                    WHERE p.stream = ?1 AND p.group_name = ?2 AND p.consumer = ?3
+                    ORDER BY e.id LIMIT ?4",
+                    params![stream, group, consumer, count],
+                )?,
+                false => stream_rows(
+                    &tx,
+                    "SELECT id, data FROM stream_entries
+                    WHERE stream = ?1 AND id > ?2
+                    ORDER BY id LIMIT ?3",
+                    params![stream, delivered, count],
+                )?,
+            };
+
+            if !pending {
+                for (id, _) in entries.iter() {
+                    tx.execute(
+                        "INSERT INTO stream_pending (stream, group_name, entry_id, consumer)
+                        VALUES (?1, ?2, ?3, ?4)",
+                        params![stream, group, id, consumer],
+                    )?;
+                }
+
+                if let Some((last, _)) = entries.last() {
+                    tx.execute(
+                        "UPDATE stream_groups SET delivered = ?3 WHERE stream = ?1 AND name = ?2",
+                        params![stream, group, last],
+                    )?;
+                }
+            }
+
+            tx.commit()?;
+
+            Ok(entries
+                .into_iter()
+                .map(|(id, data)| (id.to_string(), data.into_bytes()))
+                .collect())
+        })
+        .await
+    }
+
+    async fn ack(&self, stream: &str, group: &str, ids: &[String]) -> Result<usize, StoreError> {
+        let (stream, group) = (stream.to_string(), group.to_string());
+        let ids: Vec<i64> = ids.iter().filter_map(|id| id.parse().ok()).collect();
+
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            let mut acked = 0;
+
+            for id in ids {
+                acked += tx.execute(
+                    "DELETE FROM stream_pending WHERE stream = ?1 AND group_name = ?2 AND entry_id = ?3",
+                    params![stream, group, id],
+                )?;
+            }
+
+            tx.commit()?;
+            Ok(acked)
+        })
+        .await
+    }
+
+    async fn purge_expired(&self) -> Result<usize, StoreError> {
+        self.run(|conn| {
+            let now = now();
+            let mut purged = 0;
+
+            for route in [&ACCESS_TOKENS, &KV].iter() {
+                purged += conn.execute(
+                    &format!("DELETE FROM {} WHERE expires_at <= ?1", route.table),
+                    params![now],
+                )?;
+            }
+
+            Ok(purged)
+        })
+        .await
+    }
+
+    async fn flush(&self) -> Result<(), StoreError> {
+        self.run(|conn| {
+            conn.execute_batch(
+                "BEGIN;
+                DELETE FROM topic_plans;
+                DELETE FROM topic_voters;
+                DELETE FROM topics;
+                DELETE FROM plans;
+                DELETE FROM users;
+                DELETE FROM settings;
+                DELETE FROM access_tokens;
+                DELETE FROM kv;
+                DELETE FROM members;
+                DELETE FROM stream_pending;
+                DELETE FROM stream_groups;
+                DELETE FROM stream_entries;
+                COMMIT;",
+            )?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{PartialTopic, Plan, Settable, User};
+    use crate::store::{self, Store};
+    use std::convert::TryInto;
+
+    fn open() -> web::Data<dyn Store> {
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::open(":memory:").unwrap());
+        web::Data::from(store)
+    }
+
+    fn topic() -> Topic {
+        let partial: PartialTopic =
+            serde_json::from_value(serde_json::json!({"title": "t", "description": "d"})).unwrap();
+        partial.into()
+    }
+
+    fn plan() -> Plan {
+        let raw = serde_json::json!({"type": "simple", "data": "bread"});
+        serde_json::from_value::<crate::model::RawPlan>(raw).unwrap().try_into().unwrap()
+    }
+
+    fn links(store: &SqliteStore, table: &'static str) -> i64 {
+        let conn = store.conn.lock().unwrap();
+        conn.query_row(&format!("SELECT count(*) FROM {}", table), NO_PARAMS, |row| row.get(0))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn topics_link_the_plans_and_voters_that_exist() {
+        let sqlite = SqliteStore::open(":memory:").unwrap();
+        let store: web::Data<dyn Store> = web::Data::from(Arc::new(sqlite.clone()) as Arc<dyn Store>);
+        let user = User::new("voter".into(), "voter@example.com".into());
+
+        let mut topic = topic();
+        topic.add_plan_id(&plan().id());
+        topic.add_user(user.id());
+
+        // neither exists yet, the topic is written all the same
+        store.set(&topic.domain(), topic.json().as_bytes()).await.unwrap();
+        assert_eq!((links(&sqlite, "topic_plans"), links(&sqlite, "topic_voters")), (0, 0));
+
+        store::add(plan(), &store).await.unwrap();
+        store::add(user.clone(), &store).await.unwrap();
+        store.set(&topic.domain(), topic.json().as_bytes()).await.unwrap();
+        assert_eq!((links(&sqlite, "topic_plans"), links(&sqlite, "topic_voters")), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn deleting_a_plan_or_voter_keeps_the_topic_writable() {
+        let sqlite = SqliteStore::open(":memory:").unwrap();
+        let store: web::Data<dyn Store> = web::Data::from(Arc::new(sqlite.clone()) as Arc<dyn Store>);
+        let user = User::new("voter".into(), "voter@example.com".into());
+        store::add(plan(), &store).await.unwrap();
+        store::add(user.clone(), &store).await.unwrap();
+
+        let mut topic = topic();
+        topic.add_plan_id(&plan().id());
+        topic.add_user(user.id());
+        store.set(&topic.domain(), topic.json().as_bytes()).await.unwrap();
+
+        assert!(store::delete(plan(), &store).await.unwrap());
+        assert!(store::delete(user, &store).await.unwrap());
+        assert_eq!((links(&sqlite, "topic_plans"), links(&sqlite, "topic_voters")), (0, 0));
+
+        // the setting still names both, like on redis
+        let stored: Topic = store::get(&topic.id(), &store).await.unwrap().unwrap();
+        assert_eq!((stored.plan_ids().len(), stored.voter_ids().len()), (1, 1));
+        store.set(&topic.domain(), stored.json().as_bytes()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn compare_and_set_and_expiry() {
+        let store = open();
+
+        store.set("thing:a", b"1").await.unwrap();
+        assert!(!store.compare_and_set("thing:a", b"0", b"2").await.unwrap());
+        assert!(store.compare_and_set("thing:a", b"1", b"2").await.unwrap());
+        assert_eq!(store.get("thing:a").await.unwrap(), Some(b"2".to_vec()));
+
+        store.set_expiring("thing:b", b"1", 0).await.unwrap();
+        assert_eq!(store.get("thing:b").await.unwrap(), None);
+
+        store.set_expiring("access_token:t", b"user", 0).await.unwrap();
+        store.set_expiring("thing:c", b"1", 60).await.unwrap();
+        assert_eq!(store.purge_expired().await.unwrap(), 2);
+        assert_eq!(store.get("thing:c").await.unwrap(), Some(b"1".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn stream_groups() {
+        store::check_stream_groups(&SqliteStore::open(":memory:").unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn streams_are_trimmed() {
+        let sqlite = SqliteStore::open(":memory:").unwrap();
+        sqlite.create_group("s", "g").await.unwrap();
+        let first = sqlite.append("s", b"first").await.unwrap();
+        sqlite.read_group("s", "g", "c", 1, false).await.unwrap();
+        // another stream's entries don't count
+        sqlite.append("other", b"x").await.unwrap();
+
+        {
+            let mut conn = sqlite.conn.lock().unwrap();
+            let tx = conn.transaction().unwrap();
+            for _ in 0..STREAM_MAX_LEN {
+                tx.execute("INSERT INTO stream_entries (stream, data) VALUES ('s', 'x')", NO_PARAMS)
+                    .unwrap();
+            }
+            tx.commit().unwrap();
+        }
+
+        // until one lands on a trim
+        while sqlite.append("s", b"x").await.unwrap().parse::<i64>().unwrap() % STREAM_TRIM_EVERY != 0 {}
+
+        let count = |stream: &'static str| {
+            let conn = sqlite.conn.lock().unwrap();
+            conn.query_row(
+                "SELECT COUNT(*) FROM stream_entries WHERE stream = ?1",
+                params![stream],
+                |row| row.get::<_, i64>(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(count("s"), STREAM_MAX_LEN as i64);
+        assert_eq!(count("other"), 1);
+
+        assert!(sqlite.read_group("s", "g", "c", 10, true).await.unwrap().is_empty());
+        assert_eq!(sqlite.ack("s", "g", &[first]).await.unwrap(), 0);
+    }
+}